@@ -0,0 +1,102 @@
+use crate::mixer::{squash_fast, stretch_fast};
+
+// Bins span stretch(p) in [-8, 8] at steps of 0.5
+const APM_BINS: usize = 33;
+const APM_RANGE: f64 = 8.0;
+pub(crate) const APM_RATE: f64 = 0.05;
+const PROB_ONE: f64 = 65535.0;
+
+/// Adaptive probability map: refines an input probability within a context by
+/// interpolating between learned bins spaced evenly in the stretch domain.
+/// Bins are 16-bit probabilities so large context sets stay compact.
+#[derive(Clone)]
+pub struct Apm {
+    t: Vec<u16>,
+    n_ctx: usize,
+}
+
+/// Slot touched by the last `refine`, needed for the matching `update`.
+#[derive(Clone, Copy, Default)]
+pub struct ApmSlot {
+    idx: usize,
+    frac: f64,
+}
+
+impl Apm {
+    pub fn new(n_ctx: usize) -> Self {
+        let mut row = [0u16; APM_BINS];
+        for (i, v) in row.iter_mut().enumerate() {
+            let x = (i as f64 / (APM_BINS - 1) as f64) * 2.0 * APM_RANGE - APM_RANGE;
+            *v = (PROB_ONE / (1.0 + (-x).exp())).round() as u16;
+        }
+        let mut t = Vec::with_capacity(n_ctx * APM_BINS);
+        for _ in 0..n_ctx {
+            t.extend_from_slice(&row);
+        }
+        Self { t, n_ctx }
+    }
+
+    /// Refined probability of a 1 bit for input `p` in context `ctx`.
+    #[inline(always)]
+    pub fn refine(&self, p: f64, ctx: usize) -> (f64, ApmSlot) {
+        let x = stretch_fast(p).clamp(-APM_RANGE, APM_RANGE);
+        let pos = (x + APM_RANGE) / (2.0 * APM_RANGE) * (APM_BINS - 1) as f64;
+        let bin = (pos as usize).min(APM_BINS - 2);
+        let frac = pos - bin as f64;
+        let idx = (ctx % self.n_ctx) * APM_BINS + bin;
+        let p = (self.t[idx] as f64 * (1.0 - frac) + self.t[idx + 1] as f64 * frac) / PROB_ONE;
+        (p.clamp(1e-5, 1.0 - 1e-5), ApmSlot { idx, frac })
+    }
+
+    /// Move both interpolated bins toward the coded bit, each by its share.
+    #[inline(always)]
+    pub fn update(&mut self, slot: ApmSlot, bit: u8) {
+        let target = bit as f64 * PROB_ONE;
+        for (i, share) in [(slot.idx, 1.0 - slot.frac), (slot.idx + 1, slot.frac)] {
+            let v = &mut self.t[i];
+            let step = APM_RATE * share * (target - *v as f64);
+            *v = (*v as f64 + step).round() as u16;
+        }
+    }
+}
+
+/// Learned final blend of the SSE/APM stage outputs, per bit position.
+#[derive(Clone)]
+pub struct StageMixer {
+    w: Vec<[f64; 8]>,
+    n: usize,
+}
+
+pub(crate) const STAGE_LR: f64 = 0.002;
+
+impl StageMixer {
+    /// `n` inputs (at most 8), starting as a plain average.
+    pub fn new(n: usize) -> Self {
+        let mut init = [0.0f64; 8];
+        for v in init.iter_mut().take(n) {
+            *v = 1.0 / n as f64;
+        }
+        Self { w: vec![init; 8], n }
+    }
+
+    /// Mix stage probabilities; returns the blend and the stretched inputs.
+    #[inline(always)]
+    pub fn mix(&self, bit_pos: usize, probs: &[f64]) -> (f64, [f64; 8]) {
+        let mut xs = [0.0f64; 8];
+        let mut dot = 0.0f64;
+        for i in 0..self.n {
+            xs[i] = stretch_fast(probs[i]);
+            dot += self.w[bit_pos][i] * xs[i];
+        }
+        (squash_fast(dot), xs)
+    }
+
+    #[inline(always)]
+    pub fn update(&mut self, bit_pos: usize, xs: &[f64; 8], p: f64, bit: u8) {
+        let err = bit as f64 - p;
+        let w = &mut self.w[bit_pos];
+        for i in 0..self.n {
+            w[i] = (w[i] + STAGE_LR * err * xs[i]).clamp(-4.0, 4.0);
+        }
+    }
+}
@@ -6,7 +6,7 @@ const HALF: u64 = WHOLE >> 1;
 const QTR: u64 = WHOLE >> 2;
 const THREE_Q: u64 = 3 * QTR;
 
-/// Arithmetic encoder (32-bit precision).
+/// Arithmetic encoder (32-bit precision) writing one bit at a time.
 pub struct AEnc<'a> {
     w: &'a mut BitWriter,
     lo: u64,
@@ -27,7 +27,7 @@ impl<'a> AEnc<'a> {
     pub fn encode(&mut self, cl: u64, ch: u64, total: u64) {
         let r = self.hi - self.lo + 1;
         self.hi = self.lo + (r * ch) / total - 1;
-        self.lo = self.lo + (r * cl) / total;
+        self.lo += (r * cl) / total;
         self.renorm();
     }
 
@@ -78,7 +78,7 @@ impl<'a> AEnc<'a> {
     }
 }
 
-/// Arithmetic decoder (32-bit precision).
+/// Decoder for `AEnc`.
 pub struct ADec<'a> {
     r: BitReader<'a>,
     lo: u64,
@@ -116,7 +116,7 @@ impl<'a> ADec<'a> {
         }
         let idx = lo_s;
         self.hi = self.lo + (r * cum[idx + 1]) / total - 1;
-        self.lo = self.lo + (r * cum[idx]) / total;
+        self.lo += (r * cum[idx]) / total;
         self.renorm();
         idx
     }
@@ -130,7 +130,7 @@ impl<'a> ADec<'a> {
             self.renorm();
             0
         } else {
-            self.lo = self.lo + (r * threshold) / scale;
+            self.lo += (r * threshold) / scale;
             self.renorm();
             1
         }
@@ -157,3 +157,191 @@ impl<'a> ADec<'a> {
         }
     }
 }
+
+/// Carry-less binary arithmetic encoder over 32 bits (lpaq style). The
+/// range shrinks until the top byte of both ends agrees, and that byte goes
+/// out whole; no carries or pending bits.
+pub struct ByteEnc<'a> {
+    out: &'a mut Vec<u8>,
+    x1: u32,
+    x2: u32,
+}
+
+impl<'a> ByteEnc<'a> {
+    pub fn new(out: &'a mut Vec<u8>) -> Self {
+        Self { out, x1: 0, x2: u32::MAX }
+    }
+
+    /// Code `bit` with `p1 / scale` the probability of a 1; `p1` must lie in
+    /// `1..scale`.
+    #[inline]
+    pub fn encode_bit(&mut self, bit: u8, p1: u64, scale: u64) {
+        let xmid = split(self.x1, self.x2, p1, scale);
+        if bit != 0 {
+            self.x2 = xmid;
+        } else {
+            self.x1 = xmid + 1;
+        }
+        while (self.x1 ^ self.x2) & 0xff00_0000 == 0 {
+            self.out.push((self.x2 >> 24) as u8);
+            self.x1 <<= 8;
+            self.x2 = (self.x2 << 8) | 255;
+        }
+    }
+
+    /// One byte is enough: the top bytes of `x1` and `x2` differ, so the
+    /// next value of `x1`'s top byte, followed by the zeros the decoder
+    /// reads past the end, lies inside the range.
+    pub fn finish(&mut self) {
+        self.out.push((self.x1 >> 24) as u8 + 1);
+    }
+}
+
+/// Decoder for `ByteEnc`.
+pub struct ByteDec<'a> {
+    d: &'a [u8],
+    pos: usize,
+    x1: u32,
+    x2: u32,
+    x: u32,
+}
+
+impl<'a> ByteDec<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let mut dec = Self { d: data, pos: 0, x1: 0, x2: u32::MAX, x: 0 };
+        for _ in 0..4 {
+            dec.x = (dec.x << 8) | dec.next() as u32;
+        }
+        dec
+    }
+
+    #[inline]
+    fn next(&mut self) -> u8 {
+        let byte = self.d.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        byte
+    }
+
+    #[inline]
+    pub fn decode_bit(&mut self, p1: u64, scale: u64) -> u8 {
+        let xmid = split(self.x1, self.x2, p1, scale);
+        let bit = if self.x <= xmid {
+            self.x2 = xmid;
+            1
+        } else {
+            self.x1 = xmid + 1;
+            0
+        };
+        while (self.x1 ^ self.x2) & 0xff00_0000 == 0 {
+            self.x1 <<= 8;
+            self.x2 = (self.x2 << 8) | 255;
+            self.x = (self.x << 8) | self.next() as u32;
+        }
+        bit
+    }
+}
+
+/// Last value of the 1 side of `x1..=x2`, which takes `p1 / scale` of it.
+/// Both sides stay non-empty since `x1 < x2` after every renormalization.
+#[inline(always)]
+fn split(x1: u32, x2: u32, p1: u64, scale: u64) -> u32 {
+    debug_assert!(p1 > 0 && p1 < scale);
+    x1 + ((x2 - x1) as u64 * p1 / scale) as u32
+}
+
+/// Probability scale of the bit-tree decisions over symbol counts
+const TREE_SCALE: u64 = 1 << 16;
+
+/// Probability that the symbol lies in the upper half of `counts`.
+#[inline]
+fn upper_half_p1(counts: &[u32]) -> u64 {
+    let (lo, hi) = counts.split_at(counts.len() / 2);
+    let n0: u64 = lo.iter().map(|&c| c as u64).sum();
+    let n1: u64 = hi.iter().map(|&c| c as u64).sum();
+    ((n1 * TREE_SCALE) / (n0 + n1).max(1)).clamp(1, TREE_SCALE - 1)
+}
+
+/// Either arithmetic encoder, as chosen by `ModelConfig::coder`.
+pub enum Encoder<'a> {
+    Bits(AEnc<'a>),
+    Bytes(ByteEnc<'a>),
+}
+
+impl Encoder<'_> {
+    #[inline]
+    pub fn encode_bit(&mut self, bit: u8, p1: u64, scale: u64) {
+        match self {
+            Encoder::Bits(e) => e.encode_bit(bit, p1, scale),
+            Encoder::Bytes(e) => e.encode_bit(bit, p1, scale),
+        }
+    }
+
+    /// Code `byte` given a count for every byte value. The bit coder codes
+    /// it in one step from the cumulative counts; the byte coder, being
+    /// binary, walks the bit tree.
+    pub fn encode_counts(&mut self, counts: &[u32; 256], byte: u8) {
+        match self {
+            Encoder::Bits(e) => {
+                let cl: u64 = counts[..byte as usize].iter().map(|&c| c as u64).sum();
+                let total: u64 = cl + counts[byte as usize..].iter().map(|&c| c as u64).sum::<u64>();
+                e.encode(cl, cl + counts[byte as usize] as u64, total);
+            }
+            Encoder::Bytes(e) => {
+                let mut node: &[u32] = counts;
+                for bit_pos in 0..8 {
+                    let bit = (byte >> (7 - bit_pos)) & 1;
+                    e.encode_bit(bit, upper_half_p1(node), TREE_SCALE);
+                    let half = node.len() / 2;
+                    node = if bit != 0 { &node[half..] } else { &node[..half] };
+                }
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        match self {
+            Encoder::Bits(e) => e.finish(),
+            Encoder::Bytes(e) => e.finish(),
+        }
+    }
+}
+
+/// Either arithmetic decoder, matching `Encoder`.
+pub enum Decoder<'a> {
+    Bits(ADec<'a>),
+    Bytes(ByteDec<'a>),
+}
+
+impl Decoder<'_> {
+    #[inline]
+    pub fn decode_bit(&mut self, p1: u64, scale: u64) -> u8 {
+        match self {
+            Decoder::Bits(d) => d.decode_bit(p1, scale),
+            Decoder::Bytes(d) => d.decode_bit(p1, scale),
+        }
+    }
+
+    /// Decode a byte coded by `Encoder::encode_counts` with the same counts.
+    pub fn decode_counts(&mut self, counts: &[u32; 256]) -> u8 {
+        match self {
+            Decoder::Bits(d) => {
+                let mut cum = [0u64; 257];
+                for b in 0..256 {
+                    cum[b + 1] = cum[b] + counts[b] as u64;
+                }
+                d.decode(&cum, cum[256]) as u8
+            }
+            Decoder::Bytes(d) => {
+                let mut node: &[u32] = counts;
+                let mut byte = 0u8;
+                for _ in 0..8 {
+                    let bit = d.decode_bit(upper_half_p1(node), TREE_SCALE);
+                    let half = node.len() / 2;
+                    node = if bit != 0 { &node[half..] } else { &node[..half] };
+                    byte = (byte << 1) | bit;
+                }
+                byte
+            }
+        }
+    }
+}
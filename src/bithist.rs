@@ -0,0 +1,140 @@
+use std::sync::LazyLock;
+
+/// Largest count of the more frequent bit, indexed by the count of the less
+/// frequent one. Keeps the reachable state set under 256.
+const BOUNDS: [u8; 8] = [40, 24, 16, 10, 8, 7, 6, 5];
+
+/// Bit-history state machine: each state stands for a pair of nonstationary
+/// zero/one counts plus, once both bits have been seen, the last bit. On each
+/// bit the matching count grows and an opposite count above 2 is roughly
+/// halved, so recent bits dominate. State 0 is the empty history.
+pub struct StateTable {
+    next: [[u8; 2]; 256],
+    counts: [[u8; 2]; 256],
+    pub len: usize,
+}
+
+pub static STATES: LazyLock<StateTable> = LazyLock::new(StateTable::build);
+
+type State = (u8, u8, u8);
+
+impl StateTable {
+    fn step((n0, n1, _): State, bit: u8) -> State {
+        let (mut a, mut b) = if bit == 1 { (n1, n0) } else { (n0, n1) };
+        a += 1;
+        if b > 2 {
+            b = b / 2 + 1;
+        }
+        let top = BOUNDS.len() as u8 - 1;
+        if a >= b {
+            a = a.min(BOUNDS[b.min(top) as usize]);
+            b = b.min(top);
+        } else {
+            b = b.min(BOUNDS[a.min(top) as usize]);
+            a = a.min(top);
+        }
+        let (n0, n1) = if bit == 1 { (b, a) } else { (a, b) };
+        let last = if n0 > 0 && n1 > 0 { bit } else { 0 };
+        (n0, n1, last)
+    }
+
+    /// Enumerate reachable states breadth-first from the empty history.
+    fn build() -> Self {
+        let mut states: Vec<State> = vec![(0, 0, 0)];
+        let mut next = [[0u8; 2]; 256];
+        let mut i = 0;
+        while i < states.len() {
+            for bit in 0..2u8 {
+                let t = Self::step(states[i], bit);
+                let id = match states.iter().position(|&s| s == t) {
+                    Some(id) => id,
+                    None => {
+                        states.push(t);
+                        states.len() - 1
+                    }
+                };
+                next[i][bit as usize] = id as u8;
+            }
+            i += 1;
+        }
+        assert!(states.len() <= 256, "bit-history state table overflow");
+        let mut counts = [[0u8; 2]; 256];
+        for (c, s) in counts.iter_mut().zip(&states) {
+            *c = [s.0, s.1];
+        }
+        Self { next, counts, len: states.len() }
+    }
+
+    #[inline(always)]
+    pub fn next(&self, state: u8, bit: u8) -> u8 {
+        self.next[state as usize][bit as usize]
+    }
+
+    /// (zero count, one count) represented by a state.
+    #[inline(always)]
+    pub fn counts(&self, state: u8) -> [u8; 2] {
+        self.counts[state as usize]
+    }
+}
+
+// ── StateMap: bit-history state -> adaptive probability ──
+
+const MAP_LIMIT: u32 = 127;
+
+/// Reciprocal update rates: 65536 / (n + 1.5)
+const fn build_rates() -> [u32; MAP_LIMIT as usize + 1] {
+    let mut r = [0u32; MAP_LIMIT as usize + 1];
+    let mut n = 0;
+    while n <= MAP_LIMIT as usize {
+        r[n] = 131072 / (2 * n as u32 + 3);
+        n += 1;
+    }
+    r
+}
+
+static RATES: [u32; MAP_LIMIT as usize + 1] = build_rates();
+
+/// Maps each state to a probability that adapts to what actually followed it.
+/// Entries pack a 22-bit probability with a 10-bit hit count, which sets the
+/// update rate (1 / (count + 1.5)) until it reaches `MAP_LIMIT`.
+#[derive(Clone)]
+pub struct StateMap {
+    t: Vec<u32>,
+}
+
+impl Default for StateMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateMap {
+    pub fn new() -> Self {
+        let st = &*STATES;
+        let t = (0..256)
+            .map(|s| {
+                let [n0, n1] = st.counts(s as u8);
+                let p = ((n1 as u64 * 2 + 1) << 22) / ((n0 as u64 + n1 as u64) * 2 + 2);
+                (p.min((1 << 22) - 1) as u32) << 10
+            })
+            .collect();
+        Self { t }
+    }
+
+    /// Probability of a 1 bit in (0, 1).
+    #[inline(always)]
+    pub fn p(&self, state: u8) -> f64 {
+        let v = unsafe { *self.t.get_unchecked(state as usize) };
+        ((v >> 10) as f64 + 0.5) / (1u32 << 22) as f64
+    }
+
+    #[inline(always)]
+    pub fn update(&mut self, state: u8, bit: u8) {
+        let v = unsafe { self.t.get_unchecked_mut(state as usize) };
+        let n = *v & 1023;
+        let p = (*v >> 10) as i64;
+        let target = ((bit as i64) << 22) - bit as i64;
+        let np = p + (((target - p) * RATES[n as usize] as i64) >> 16);
+        *v = ((np as u32) << 10) | (n + (n < MAP_LIMIT) as u32);
+    }
+}
@@ -37,6 +37,12 @@ impl BitWriter {
     }
 }
 
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Bit reader — MSB-first, reads from a byte slice.
 pub struct BitReader<'a> {
     d: &'a [u8],
@@ -0,0 +1,299 @@
+use crate::bithist::{StateMap, STATES};
+
+// ── Bucketed layout ──
+// A slot holds one checksum byte plus the 15 states of a nibble's bit tree;
+// four slots share one 64-byte cache line.
+const SLOT_BYTES: usize = 16;
+const SLOTS_PER_BUCKET: usize = 4;
+const BUCKET_BYTES: usize = SLOT_BYTES * SLOTS_PER_BUCKET;
+
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+pub(crate) struct Bucket([[u8; SLOT_BYTES]; SLOTS_PER_BUCKET]);
+
+/// Hashed bit-context storage shared by all bit models.
+///
+/// `slots` turns a model's byte-level context into the position of its entry
+/// for the current bit; `predict` and `update` then work on that position.
+#[derive(Clone)]
+pub(crate) enum BitTable {
+    /// Raw zero/one counts, direct-mapped and halved once they exceed 16 (V8/V9)
+    Counts(Vec<[u16; 2]>),
+    /// Direct-mapped bit-history states, one StateMap per model
+    States { t: Vec<u8>, maps: Vec<StateMap> },
+    /// Bit-history states in checksummed cache-line buckets, looked up once per
+    /// nibble. `cur` is each model's current slot, as a flat byte offset.
+    /// With `skip_inactive`, models without a context predict 1/2 and are not
+    /// trained instead of sharing the slot of context hash 0.
+    Buckets {
+        t: Vec<Bucket>,
+        maps: Vec<StateMap>,
+        cur: Vec<usize>,
+        skip_inactive: bool,
+    },
+}
+
+/// Slot position standing for "no context" when inactive models are skipped
+const NO_SLOT: usize = usize::MAX;
+
+/// Mix a byte-level context with the nibble it continues into a bucket hash.
+#[inline(always)]
+fn nibble_hash(base: u32, node: u32) -> u32 {
+    let mut h = base ^ node.wrapping_mul(0x9E3779B1);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846CA68B);
+    h ^ (h >> 16)
+}
+
+impl BitTable {
+    /// Count table of `1 << bits` entries.
+    pub(crate) fn counts(bits: u8) -> Self {
+        BitTable::Counts(vec![[0u16; 2]; 1 << bits])
+    }
+
+    /// State table of `1 << bits` bytes.
+    pub(crate) fn states(bits: u8, n_models: usize) -> Self {
+        BitTable::States {
+            t: vec![0u8; 1 << bits],
+            maps: vec![StateMap::new(); n_models],
+        }
+    }
+
+    /// Bucketed state table of `1 << bits` bytes.
+    pub(crate) fn buckets(bits: u8, n_models: usize, skip_inactive: bool) -> Self {
+        BitTable::Buckets {
+            t: vec![Bucket([[0u8; SLOT_BYTES]; SLOTS_PER_BUCKET]); (1 << bits) / BUCKET_BYTES],
+            maps: vec![StateMap::new(); n_models],
+            cur: vec![0; n_models],
+            skip_inactive,
+        }
+    }
+
+    /// Entry positions for the bit at `node`, given each model's byte-level
+    /// base hash. Inactive models all use the context hash 0.
+    #[inline(always)]
+    pub(crate) fn slots(
+        &mut self,
+        bases: &[u32],
+        active: &[bool],
+        node: u32,
+        bit_pos: usize,
+        out: &mut [usize],
+    ) {
+        match self {
+            BitTable::Counts(t) => Self::direct_slots(t.len(), bases, active, node, out),
+            BitTable::States { t, .. } => Self::direct_slots(t.len(), bases, active, node, out),
+            BitTable::Buckets { t, cur, skip_inactive, .. } => {
+                if bit_pos == 0 || bit_pos == 4 {
+                    for m in 0..out.len() {
+                        let slot = if active[m] {
+                            Self::find_slot(t, nibble_hash(bases[m], node), &cur[..m])
+                        } else if *skip_inactive {
+                            NO_SLOT
+                        } else {
+                            Self::find_slot(t, nibble_hash(0, node), &cur[..m])
+                        };
+                        cur[m] = slot;
+                    }
+                }
+                // Node within the current nibble's tree, 1..=15
+                let depth = bit_pos & 3;
+                let nib_node = (1 << depth) | (node as usize & ((1 << depth) - 1));
+                for m in 0..out.len() {
+                    out[m] = if cur[m] == NO_SLOT { NO_SLOT } else { cur[m] + nib_node };
+                }
+            }
+        }
+    }
+
+    /// Slots of a direct-mapped table of `size` entries.
+    #[inline(always)]
+    fn direct_slots(size: usize, bases: &[u32], active: &[bool], node: u32, out: &mut [usize]) {
+        let node_part = node.wrapping_mul(2654435761);
+        for m in 0..out.len() {
+            let h = if active[m] { bases[m] ^ node_part } else { 0 };
+            out[m] = h as usize & (size - 1);
+        }
+    }
+
+    /// Find the slot whose checksum matches, or claim the least-used slot of
+    /// the bucket that no model in `claimed` took in this lookup. Returns the
+    /// slot's flat byte offset, or `NO_SLOT` if the other models hold the
+    /// whole bucket.
+    fn find_slot(t: &mut [Bucket], h: u32, claimed: &[usize]) -> usize {
+        let b = (h as usize) & (t.len() - 1);
+        let chk = (h >> 24) as u8;
+        let bucket = &mut t[b].0;
+        for (i, slot) in bucket.iter().enumerate() {
+            if slot[0] == chk {
+                return (b * SLOTS_PER_BUCKET + i) * SLOT_BYTES;
+            }
+        }
+        // Priority: how much history the slot's first node has seen. Ranking by
+        // more of the slot's nodes or by recency, and exact 32-bit checks in
+        // place of the checksum byte, each changed the output by under 0.1%.
+        let mut victim = NO_SLOT;
+        let mut least = u32::MAX;
+        for (i, slot) in bucket.iter().enumerate() {
+            let pos = (b * SLOTS_PER_BUCKET + i) * SLOT_BYTES;
+            if claimed.contains(&pos) {
+                continue;
+            }
+            let [n0, n1] = STATES.counts(slot[1]);
+            let used = n0 as u32 + n1 as u32;
+            if used < least {
+                least = used;
+                victim = i;
+            }
+        }
+        if victim == NO_SLOT {
+            return NO_SLOT;
+        }
+        bucket[victim] = [0u8; SLOT_BYTES];
+        bucket[victim][0] = chk;
+        (b * SLOTS_PER_BUCKET + victim) * SLOT_BYTES
+    }
+
+    #[inline(always)]
+    fn bucket_byte(t: &[Bucket], pos: usize) -> &u8 {
+        unsafe {
+            t.get_unchecked(pos / BUCKET_BYTES)
+                .0
+                .get_unchecked((pos / SLOT_BYTES) % SLOTS_PER_BUCKET)
+                .get_unchecked(pos % SLOT_BYTES)
+        }
+    }
+
+    #[inline(always)]
+    fn bucket_byte_mut(t: &mut [Bucket], pos: usize) -> &mut u8 {
+        unsafe {
+            t.get_unchecked_mut(pos / BUCKET_BYTES)
+                .0
+                .get_unchecked_mut((pos / SLOT_BYTES) % SLOTS_PER_BUCKET)
+                .get_unchecked_mut(pos % SLOT_BYTES)
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn predict(&self, m: usize, idx: usize) -> f64 {
+        match self {
+            BitTable::Counts(t) => {
+                let entry = unsafe { t.get_unchecked(idx) };
+                let total = (entry[0] as u32 + entry[1] as u32) as f64;
+                if total == 0.0 {
+                    0.5
+                } else {
+                    (entry[1] as f64 + 0.5) / (total + 1.0)
+                }
+            }
+            BitTable::States { t, maps } => maps[m].p(unsafe { *t.get_unchecked(idx) }),
+            BitTable::Buckets { .. } if idx == NO_SLOT => 0.5,
+            BitTable::Buckets { t, maps, .. } => maps[m].p(*Self::bucket_byte(t, idx)),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn update(&mut self, m: usize, idx: usize, bit: u8) {
+        match self {
+            BitTable::Counts(t) => {
+                let entry = unsafe { t.get_unchecked_mut(idx) };
+                let bit = bit as usize;
+                entry[bit] = entry[bit].saturating_add(1);
+                if entry[0] as u32 + entry[1] as u32 > 16 {
+                    entry[0] = (entry[0] + 1) >> 1;
+                    entry[1] = (entry[1] + 1) >> 1;
+                }
+            }
+            BitTable::States { t, maps } => {
+                let state = unsafe { t.get_unchecked_mut(idx) };
+                maps[m].update(*state, bit);
+                *state = STATES.next(*state, bit);
+            }
+            BitTable::Buckets { .. } if idx == NO_SLOT => {}
+            BitTable::Buckets { t, maps, .. } => {
+                let state = Self::bucket_byte_mut(t, idx);
+                maps[m].update(*state, bit);
+                *state = STATES.next(*state, bit);
+            }
+        }
+    }
+
+    /// Pretraining update. Counts accumulate unhalved until `finish_pretrain`.
+    #[inline(always)]
+    pub(crate) fn train(&mut self, m: usize, idx: usize, bit: u8) {
+        match self {
+            BitTable::Counts(t) => {
+                let entry = unsafe { t.get_unchecked_mut(idx) };
+                entry[bit as usize] = entry[bit as usize].saturating_add(1);
+            }
+            _ => self.update(m, idx, bit),
+        }
+    }
+
+    pub(crate) fn finish_pretrain(&mut self) {
+        if let BitTable::Counts(t) = self {
+            for entry in t.iter_mut() {
+                entry[0] /= 2;
+                entry[1] /= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bases of `n` contexts whose first nibble lands in one bucket of a
+    /// `1 << bits` byte table, each with its own checksum.
+    fn colliding_bases(bits: u8, n: usize) -> Vec<u32> {
+        let buckets = (1usize << bits) / BUCKET_BYTES;
+        let bucket = |base: u32| nibble_hash(base, 1) as usize & (buckets - 1);
+        let chk = |base: u32| (nibble_hash(base, 1) >> 24) as u8;
+        let mut bases = vec![1u32];
+        let mut base = 2;
+        while bases.len() < n {
+            if bucket(base) == bucket(1) && chk(base) != 0 && bases.iter().all(|&b| chk(b) != chk(base)) {
+                bases.push(base);
+            }
+            base += 1;
+        }
+        bases
+    }
+
+    #[test]
+    fn colliding_contexts_keep_their_slots() {
+        let bases = colliding_bases(16, 2);
+        let mut table = BitTable::buckets(16, 2, true);
+        let mut slots = [0usize; 2];
+        for _ in 0..3 {
+            table.slots(&bases, &[true, true], 1, 0, &mut slots);
+            assert_ne!(slots[0], slots[1]);
+            for bit_pos in 0..4 {
+                let node = 1 << bit_pos;
+                table.slots(&bases, &[true, true], node, bit_pos, &mut slots);
+                table.update(0, slots[0], 1);
+                table.update(1, slots[1], 0);
+            }
+        }
+        // Each context kept its own history across the three nibbles
+        table.slots(&bases, &[true, true], 1, 0, &mut slots);
+        assert!(table.predict(0, slots[0]) > 0.5);
+        assert!(table.predict(1, slots[1]) < 0.5);
+    }
+
+    #[test]
+    fn full_bucket_leaves_the_last_context_out() {
+        let bases = colliding_bases(16, SLOTS_PER_BUCKET + 1);
+        let mut table = BitTable::buckets(16, bases.len(), true);
+        let mut slots = vec![0usize; bases.len()];
+        table.slots(&bases, &vec![true; bases.len()], 1, 0, &mut slots);
+        let (held, rest) = slots.split_at(SLOTS_PER_BUCKET);
+        for (i, &slot) in held.iter().enumerate() {
+            assert!(slot != NO_SLOT && !held[..i].contains(&slot));
+        }
+        assert_eq!(rest, [NO_SLOT]);
+    }
+}
@@ -26,7 +26,7 @@ const fn build_char_freq() -> [u32; 256] {
     // Word tokens (most common first): max(3, 60 - i) for i in 0..127
     let mut i = 0usize;
     while i < 127 {
-        let v = if 60 > i as u32 { 60 - i as u32 } else { 0 };
+        let v = 60u32.saturating_sub(i as u32);
         f[129 + i] = if v >= 3 { v } else { 3 };
         i += 1;
     }
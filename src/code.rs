@@ -0,0 +1,250 @@
+use crate::dict::CAP_MARKER;
+
+/// Lexical state of source code.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Lex {
+    #[default]
+    Code,
+    /// Inside a string literal closed by the given quote
+    Str(u8),
+    /// Escape inside a string literal
+    Escape(u8),
+    /// After a `'`: the first byte and the number of bytes read. The quote
+    /// opened a char literal only if it closes within one character (or an
+    /// escape); lifetimes and apostrophes fall back to code.
+    Char(u8, u8),
+    LineComment,
+    /// Inside `/* */`; true after a `*` within the comment
+    BlockComment(bool),
+    /// After a `#` that may open a line comment: `#[` and `#![` are
+    /// attributes, a letter right after a `#` in column 0 starts a
+    /// preprocessor line, anything else is a comment. True in column 0.
+    Hash(bool),
+    /// Preprocessor line (`#include`, `#define`), continued by a trailing `\`
+    Preproc,
+}
+
+impl Lex {
+    fn id(self) -> u32 {
+        match self {
+            Lex::Code | Lex::Hash(_) => 0,
+            Lex::Str(_) | Lex::Escape(_) | Lex::Char(..) => 1,
+            Lex::LineComment => 2,
+            Lex::BlockComment(_) => 3,
+            Lex::Preproc => 4,
+        }
+    }
+}
+
+const MAX_NESTING: usize = 32;
+
+/// Source-code structure: indentation, bracket nesting, string and comment
+/// state, and identifiers.
+///
+/// Identifiers run over letters, digits, `_` and dictionary tokens (so
+/// `use_the_index` stays one identifier after preprocessing), unlike the
+/// whitespace-delimited word hash.
+#[derive(Clone, Default)]
+pub struct CodeContexts {
+    lex: Lex,
+    /// Open brackets, innermost last
+    brackets: Vec<u8>,
+    /// Leading whitespace of the current line (a tab counts 4)
+    indent: u32,
+    prev_indent: u32,
+    at_line_start: bool,
+    /// Hash of the identifier being read, 0 outside identifiers
+    ident: u32,
+    /// Hash of the last complete identifier
+    prev_ident: u32,
+    last: u8,
+}
+
+impl CodeContexts {
+    pub fn new() -> Self {
+        Self {
+            at_line_start: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn update(&mut self, byte: u8) {
+        let is_ident = byte.is_ascii_alphanumeric() || byte == b'_' || byte >= CAP_MARKER;
+        if is_ident {
+            self.ident = (self.ident ^ byte as u32).wrapping_mul(16777619) | 1;
+        } else if self.ident != 0 {
+            self.prev_ident = self.ident;
+            self.ident = 0;
+        }
+
+        let line_start = self.at_line_start;
+        if byte == b'\n' {
+            self.prev_indent = self.indent;
+            self.indent = 0;
+            self.at_line_start = true;
+        } else if self.at_line_start {
+            match byte {
+                b' ' => self.indent += 1,
+                b'\t' => self.indent += 4,
+                _ => self.at_line_start = false,
+            }
+        }
+
+        self.lex = match self.lex {
+            Lex::Code => self.code(byte, line_start),
+            Lex::Char(_, 0) if byte == b'\n' => Lex::Code,
+            Lex::Char(_, 0) => Lex::Char(byte, 1),
+            Lex::Char(first, len) => {
+                let limit = match first {
+                    b'\\' => 10,
+                    0x80.. => 4,
+                    _ => 1,
+                };
+                if byte == b'\'' && !(first == b'\\' && len == 1) {
+                    Lex::Code
+                } else if byte == b'\n' || len >= limit {
+                    self.code(byte, line_start)
+                } else {
+                    Lex::Char(first, len + 1)
+                }
+            }
+            Lex::Hash(column0) => match byte {
+                b'!' => Lex::Hash(column0),
+                b'[' => {
+                    self.update_brackets(byte);
+                    Lex::Code
+                }
+                b'\n' => Lex::Code,
+                _ if column0 && byte.is_ascii_alphabetic() => Lex::Preproc,
+                _ => Lex::LineComment,
+            },
+            Lex::Preproc if byte == b'\n' && self.last != b'\\' => Lex::Code,
+            Lex::Str(q) if byte == b'\\' => Lex::Escape(q),
+            Lex::Str(q) if byte == q || byte == b'\n' => Lex::Code,
+            Lex::Escape(q) => Lex::Str(q),
+            Lex::LineComment if byte == b'\n' => Lex::Code,
+            Lex::BlockComment(true) if byte == b'/' => Lex::Code,
+            Lex::BlockComment(_) => Lex::BlockComment(byte == b'*'),
+            lex => lex,
+        };
+        self.last = byte;
+    }
+
+    /// Transition out of [`Lex::Code`] on `byte`.
+    fn code(&mut self, byte: u8, line_start: bool) -> Lex {
+        match byte {
+            b'"' | b'`' => Lex::Str(byte),
+            b'\'' => Lex::Char(0, 0),
+            b'/' if self.last == b'/' => Lex::LineComment,
+            b'*' if self.last == b'/' => Lex::BlockComment(false),
+            b'#' if line_start || self.last == b' ' => Lex::Hash(line_start && self.indent == 0),
+            _ => {
+                self.update_brackets(byte);
+                Lex::Code
+            }
+        }
+    }
+
+    fn update_brackets(&mut self, byte: u8) {
+        match byte {
+            b'(' | b'[' | b'{' => {
+                if self.brackets.len() == MAX_NESTING {
+                    self.brackets.remove(0);
+                }
+                self.brackets.push(byte);
+            }
+            b')' | b']' | b'}' => {
+                self.brackets.pop();
+            }
+            _ => {}
+        }
+    }
+
+    /// Byte-level base hashes for the next byte: (indentation + nesting +
+    /// lexical state + previous byte, identifier prefix, previous identifier +
+    /// identifier prefix).
+    pub fn contexts(&self) -> [Option<u32>; 3] {
+        let top = self.brackets.last().copied().unwrap_or(0) as u32;
+        let depth = self.brackets.len() as u32;
+        let indent = if self.at_line_start {
+            self.prev_indent.min(63) << 6 | self.indent.min(63)
+        } else {
+            self.indent.min(63)
+        };
+        let layout = (indent << 20
+            ^ (self.at_line_start as u32) << 19
+            ^ depth << 14
+            ^ top << 6
+            ^ self.lex.id() << 4)
+            .wrapping_mul(2654435761)
+            ^ (self.last as u32).wrapping_mul(2246822519);
+        let ident = (self.ident ^ self.lex.id()).wrapping_mul(3266489917);
+        let pair = (self.prev_ident.wrapping_mul(2654435761) ^ self.ident).wrapping_mul(2246822519)
+            ^ if self.ident == 0 { self.last as u32 } else { 0 };
+        [
+            Some(layout.wrapping_mul(16777619) ^ 0x01234567),
+            Some(ident.wrapping_mul(16777619) ^ 0x12345670),
+            Some(pair.wrapping_mul(16777619) ^ 0x23456701),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(text: &str) -> CodeContexts {
+        let mut code = CodeContexts::new();
+        for &b in text.as_bytes() {
+            code.update(b);
+        }
+        code
+    }
+
+    fn lex_after(text: &str) -> Lex {
+        after(text).lex
+    }
+
+    #[test]
+    fn hash_comments() {
+        assert!(lex_after("print(x) #include") == Lex::LineComment);
+        assert!(lex_after("x = 1\n# note") == Lex::LineComment);
+        assert!(lex_after("    # indented") == Lex::LineComment);
+        assert!(lex_after("x = 1  # trailing") == Lex::LineComment);
+        assert!(lex_after("#!/bin/sh") == Lex::LineComment);
+    }
+
+    #[test]
+    fn preprocessor_lines() {
+        assert!(lex_after("#include <stdio.h>") == Lex::Preproc);
+        assert!(lex_after("#define MAX(a, b) \\\n  ((a) > (b)") == Lex::Preproc);
+        assert!(lex_after("#include <a>\nint x") == Lex::Code);
+        assert!(after("#define X(a) (a\nint y").brackets.is_empty());
+    }
+
+    #[test]
+    fn char_literals_close_early() {
+        assert!(lex_after("let c = 'a'; x") == Lex::Code);
+        assert!(lex_after("'\\'' + '\\\\' + '\\u{1F600}' + 'é'; x") == Lex::Code);
+        let code = after("fn f<'a>(s: &'a [u8]) { x");
+        assert!(code.lex == Lex::Code && code.brackets == b"{");
+        assert!(lex_after("// don't\nit's a") == Lex::Code);
+        assert!(lex_after("let c = 'a") == Lex::Char(b'a', 1));
+    }
+
+    #[test]
+    fn block_comments() {
+        assert!(lex_after("/*/ x") == Lex::BlockComment(false));
+        assert!(lex_after("/*/ x */ y") == Lex::Code);
+        assert!(lex_after("/**/ y") == Lex::Code);
+        assert!(lex_after("/* a ** b */ y") == Lex::Code);
+    }
+
+    #[test]
+    fn attributes_are_code() {
+        assert!(lex_after("#[derive(Clone)]") == Lex::Code);
+        assert!(lex_after("    #[inline]\n    fn f") == Lex::Code);
+        assert!(lex_after("#![allow(dead_code)]") == Lex::Code);
+        assert!(lex_after("a#b") == Lex::Code);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::arithmetic::{Decoder, Encoder};
+use crate::config::{ModelConfig, CODEC_PPM, HASH_NEWEST_FIRST};
+use crate::history::History;
+use crate::lzp::LZP;
+use crate::mixer::ContextMixer;
+use crate::ppm::PPM;
+
+/// PPM coding whole bytes, with the LZP prediction blended into its
+/// distribution. The V7 coder, and the fast codec of V10.
+#[derive(Clone)]
+pub struct PpmCoder {
+    ppm: PPM,
+    lzp: LZP,
+    hist: History,
+}
+
+impl PpmCoder {
+    pub fn with_config(cfg: &ModelConfig) -> Self {
+        let ppm = PPM::with_config(cfg);
+        let mut hashed: Vec<usize> = ppm.orders().to_vec();
+        hashed.extend(LZP::hash_lengths());
+        Self {
+            ppm,
+            lzp: LZP::with_config(cfg),
+            hist: History::new(cfg.history_window, &hashed, cfg.context_hash == HASH_NEWEST_FIRST),
+        }
+    }
+
+    pub fn pretrain(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.ppm.update(&self.hist, byte);
+            self.hist.push(byte);
+            self.lzp.update(&self.hist);
+        }
+        self.ppm.finish_pretrain(data);
+    }
+
+    pub fn encode_byte(&mut self, byte: u8, enc: &mut Encoder) {
+        self.ppm.encode_byte(&self.hist, byte, enc, self.lzp.pred, self.lzp.pred_len);
+        self.hist.push(byte);
+        self.lzp.update(&self.hist);
+    }
+
+    pub fn decode_byte(&mut self, dec: &mut Decoder) -> u8 {
+        let byte = self.ppm.decode_byte(&self.hist, dec, self.lzp.pred, self.lzp.pred_len);
+        self.hist.push(byte);
+        self.lzp.update(&self.hist);
+        byte
+    }
+}
+
+/// Whatever codes the blocks of a V10 file, as chosen by `ModelConfig::codec`.
+#[derive(Clone)]
+pub enum BlockCoder {
+    Mixer(Box<ContextMixer>),
+    Ppm(Box<PpmCoder>),
+}
+
+impl BlockCoder {
+    pub fn with_config(cfg: &ModelConfig) -> Self {
+        match cfg.codec {
+            CODEC_PPM => BlockCoder::Ppm(Box::new(PpmCoder::with_config(cfg))),
+            _ => BlockCoder::Mixer(Box::new(ContextMixer::with_config(cfg))),
+        }
+    }
+
+    pub fn pretrain(&mut self, data: &[u8]) {
+        match self {
+            BlockCoder::Mixer(cm) => cm.pretrain(data),
+            BlockCoder::Ppm(pc) => pc.pretrain(data),
+        }
+    }
+
+    pub fn encode_byte(&mut self, byte: u8, enc: &mut Encoder) {
+        match self {
+            BlockCoder::Mixer(cm) => cm.encode_byte(byte, enc),
+            BlockCoder::Ppm(pc) => pc.encode_byte(byte, enc),
+        }
+    }
+
+    pub fn decode_byte(&mut self, dec: &mut Decoder) -> u8 {
+        match self {
+            BlockCoder::Mixer(cm) => cm.decode_byte(dec),
+            BlockCoder::Ppm(pc) => pc.decode_byte(dec),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// Field delimiters the column model recognises
+const DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+/// Bytes of a line kept for the row below and counted for its delimiter;
+/// longer lines (minified or binary data) only advance the column
+const MAX_LINE: usize = 4096;
+
+/// Column contexts for tabular text (CSV, TSV and fixed-width records).
+///
+/// Tracks the position in the current line, the field index under the
+/// delimiter that dominated the previous line (quoted fields are skipped),
+/// and the previous line itself, so the byte at the same column one row up
+/// can serve as context.
+#[derive(Clone, Default)]
+pub struct ColumnContexts {
+    /// First `MAX_LINE` bytes of the current line
+    line: Vec<u8>,
+    prev_line: Vec<u8>,
+    /// Position in the current line
+    col: usize,
+    /// Occurrences of each of `DELIMITERS` in `line`
+    delim_counts: [u32; DELIMITERS.len()],
+    /// Delimiter of the previous line, 0 if it had none
+    delim: u8,
+    field: u32,
+    /// Position within the current field
+    field_pos: u32,
+    in_quotes: bool,
+}
+
+impl ColumnContexts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.delim = self
+                .delim_counts
+                .iter()
+                .zip(DELIMITERS)
+                .max()
+                .filter(|&(&count, _)| count > 0)
+                .map_or(0, |(_, d)| d);
+            std::mem::swap(&mut self.line, &mut self.prev_line);
+            self.line.clear();
+            self.col = 0;
+            self.delim_counts = [0; DELIMITERS.len()];
+            self.field = 0;
+            self.field_pos = 0;
+            self.in_quotes = false;
+            return;
+        }
+        if self.line.len() < MAX_LINE {
+            self.line.push(byte);
+            if let Some(i) = DELIMITERS.iter().position(|&d| d == byte) {
+                self.delim_counts[i] += 1;
+            }
+        }
+        self.col += 1;
+        if byte == b'"' {
+            self.in_quotes = !self.in_quotes;
+        }
+        if byte == self.delim && !self.in_quotes {
+            self.field += 1;
+            self.field_pos = 0;
+        } else {
+            self.field_pos += 1;
+        }
+    }
+
+    /// Byte-level base hashes for the next byte: (field index + position in
+    /// field, byte above + previous byte, byte above + position in line).
+    /// The vertical contexts are `None` when the previous row is too short.
+    pub fn contexts(&self, last: u8) -> [Option<u32>; 3] {
+        let col = self.col;
+        let field = (self.field.min(255) << 16 | self.field_pos.min(63) << 8 | last as u32)
+            .wrapping_mul(2654435761);
+        let above = self.prev_line.get(col).map(|&a| a as u32);
+        let vertical = above.map(|a| {
+            let h = (a << 8 | last as u32).wrapping_mul(2246822519) ^ (self.delim as u32) << 24;
+            h.wrapping_mul(16777619) ^ 0xBCDEF012
+        });
+        let aligned = above.map(|a| {
+            let h = ((col.min(4095) as u32) << 8 | a).wrapping_mul(3266489917);
+            h.wrapping_mul(16777619) ^ 0xCDEF0123
+        });
+        [Some(field.wrapping_mul(16777619) ^ 0xDEF01234), vertical, aligned]
+    }
+}
@@ -0,0 +1,587 @@
+use crate::matcher::MATCH_LONGEST;
+use crate::ppm::MAX_PPM_ORDERS;
+
+/// Model configuration recorded in the V10 header so the decoder can rebuild
+/// exactly the same model the encoder used.
+///
+/// Fields are serialized in declaration order, one after another (lists as a
+/// length byte followed by the entries). The reader requires every field.
+/// V10 is frozen (`tests/data/sample.v10` must keep decoding): a new field,
+/// or a model change that alters what a V10 file decodes to, goes under a
+/// new format version instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelConfig {
+    /// Highest context order for PPM and the hashed order models, used when
+    /// the explicit order lists below are empty
+    pub max_order: u8,
+    /// Extra mixer weight-set selectors (`SEL_*` bits). Zero means a single
+    /// weight set per bit position, as in V8/V9.
+    pub selectors: u8,
+    /// APM stages chained after the SSE (`APM_*` bits). Zero keeps the fixed
+    /// V8/V9 blend of mixer and SSE output.
+    pub apm: u8,
+    /// Representation of the hashed bit contexts (`TABLE_*`)
+    pub bit_table: u8,
+    /// Optional context models feeding extra mixer inputs (`MODEL_*` bits)
+    pub models: u8,
+    /// Ascending context orders of the hashed order models (empty: 0..=max_order)
+    pub orders: Vec<u8>,
+    /// Ascending context orders PPM interpolates over (empty: 0..=max_order)
+    pub ppm_orders: Vec<u8>,
+    /// Match models feeding direct mixer inputs: minimum length, optionally
+    /// `| MATCH_LONGEST`
+    pub matches: Vec<u8>,
+    /// log2 of each match model's table entries
+    pub match_table_bits: u8,
+    /// log2 of the LZP window in bytes; 0 keeps the unbounded V8/V9 LZP
+    pub lzp_window: u8,
+    /// log2 of the bounded LZP's table entries
+    pub lzp_table: u8,
+    /// Candidates the bounded LZP keeps and verifies per hash (power of two)
+    pub lzp_chain: u8,
+    /// log2 of the bytes of history kept for LZP and the match models; 0
+    /// keeps everything (required by the unbounded LZP)
+    pub history_window: u8,
+    /// log2 of the PPM table memory budget in bytes; 0 leaves PPM unbounded
+    pub ppm_budget: u8,
+    /// What PPM does at its budget (`PPM_PRUNE` or `PPM_RESTART`)
+    pub ppm_policy: u8,
+    /// How PPM combines its orders (`PPM_INTERPOLATED`, `PPM_ESCAPE` or
+    /// `PPM_ADAPTIVE`)
+    pub ppm_model: u8,
+    /// What codes each block (`CODEC_MIXER` or `CODEC_PPM`)
+    pub codec: u8,
+    /// log2 of the total at which a PPM context halves its counts; 0 never
+    /// halves
+    pub ppm_halve: u8,
+    /// 1: the mixer reads PPM over the bit tree from sparse terms; 0 sums
+    /// its full 256-entry distribution per byte (the same up to rounding)
+    pub ppm_tree: u8,
+    /// How the mixer and residual NN learning rates evolve (`LR_*`)
+    pub lr_schedule: u8,
+    /// 1: everything from stretch to the coded probability runs in integer
+    /// arithmetic, so the file decodes bit-exactly on any platform (the
+    /// models' own `f64` outputs use only correctly rounded IEEE operations;
+    /// see `FixedMix`); 0 uses `f64` with tables built from `exp` and `ln`
+    pub fixed_point: u8,
+    /// Arithmetic coder of the blocks (`CODER_BITS` or `CODER_BYTES`)
+    pub coder: u8,
+    /// log2 of the scale of the probabilities the mixer hands the coder
+    /// (15 in V8/V9); each bit costs at least about 2^-`prob_bits`
+    /// / ln 2 bits
+    pub prob_bits: u8,
+    /// log2 of the bit table's size: bytes for the state tables, entries
+    /// for the count table
+    pub bit_table_bits: u8,
+    /// How the shared history hashes the suffixes the models read (`HASH_*`)
+    pub context_hash: u8,
+    /// 1: a small neural network over the stretched inputs adds a learned
+    /// correction to the mixer's logit (V8/V9); 0 mixes linearly only
+    pub residual_nn: u8,
+    /// Compression level the configuration was built from (1–9), or 0 if
+    /// it was not built from one. Informational: the fields above are what
+    /// the decoder uses.
+    pub level: u8,
+}
+
+/// Longest context any order list may use
+pub const MAX_CONTEXT_ORDER: u8 = 32;
+/// Most entries in the hashed order list
+pub const MAX_ORDER_MODELS: usize = 16;
+/// Most match models
+pub const MAX_MATCH_MODELS: usize = 4;
+/// Match table sizes, as log2 of the entries
+pub const MIN_MATCH_TABLE_BITS: u8 = 10;
+pub const MAX_MATCH_TABLE_BITS: u8 = 28;
+/// Coder probability resolutions, in bits
+pub const MIN_PROB_BITS: u8 = 15;
+pub const MAX_PROB_BITS: u8 = 24;
+/// FNV-1a over each suffix, oldest byte first, every length rehashed per
+/// byte (V8/V9)
+pub const HASH_FNV: u8 = 0;
+/// FNV-1a steps newest byte first, each length extending the one below it
+pub const HASH_NEWEST_FIRST: u8 = 1;
+/// Bit table sizes, as log2
+pub const MIN_BIT_TABLE_BITS: u8 = 16;
+pub const MAX_BIT_TABLE_BITS: u8 = 28;
+/// A bit table fitted to its input gets up to 2^this bytes per input byte
+const BIT_TABLE_BYTES_PER_INPUT_BITS: u8 = 7;
+/// A fitted match table gets up to 2^this entries per input byte
+const MATCH_TABLE_ENTRIES_PER_INPUT_BITS: u8 = 2;
+
+/// Compression levels: 1 is fastest, 9 strongest
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 9;
+/// Level of `ModelConfig::default()`
+pub const DEFAULT_LEVEL: u8 = 6;
+
+/// Weight set chosen by the class of the previous byte
+pub const SEL_CLASS: u8 = 1;
+/// Weight set chosen by the LZP match-length bucket
+pub const SEL_MATCH: u8 = 2;
+/// Weight set chosen by the previous byte (order-1)
+pub const SEL_ORDER1: u8 = 4;
+/// Weight set chosen by the markup model's tag mode and block kind (levels
+/// 6-9, where the markup model runs with it)
+pub const SEL_MARKUP: u8 = 8;
+pub const SEL_ALL: u8 = SEL_CLASS | SEL_MATCH | SEL_ORDER1 | SEL_MARKUP;
+
+/// APM keyed by the previous byte and the partial byte
+pub const APM_ORDER1: u8 = 1;
+/// APM keyed by a hash of the previous two bytes and the partial byte
+pub const APM_ORDER2: u8 = 2;
+/// APM keyed by the LZP match length and expected bit
+pub const APM_MATCH: u8 = 4;
+/// High-resolution APM on the final probability, keyed by the run of
+/// confidently coded bits, reaching down to 2^-`prob_bits`
+pub const APM_FINAL: u8 = 8;
+pub const APM_ALL: u8 = APM_ORDER1 | APM_ORDER2 | APM_MATCH | APM_FINAL;
+
+/// Zero/one counts per slot, halved past 16 (V8/V9)
+pub const TABLE_COUNTS: u8 = 0;
+/// Bit-history states with adaptive state-to-probability maps
+pub const TABLE_STATES: u8 = 1;
+/// Bit-history states in checksummed cache-line buckets, one lookup per nibble
+pub const TABLE_BUCKETED: u8 = 2;
+/// Bucketed, with models that have no context left out instead of sharing one
+pub const TABLE_BUCKETED_ACTIVE: u8 = 3;
+
+/// PPM drops rarely seen contexts when over budget
+pub const PPM_PRUNE: u8 = 0;
+/// PPM starts over from recent history when over budget
+pub const PPM_RESTART: u8 = 1;
+
+/// PPM interpolates every order with Kneser-Ney discounting (V7–V9)
+pub const PPM_INTERPOLATED: u8 = 0;
+/// PPM codes escapes, with full exclusion and secondary escape estimation
+pub const PPM_ESCAPE: u8 = 1;
+/// PPM interpolates with modified Kneser-Ney discounts per order and count,
+/// estimated online from its count-of-counts
+pub const PPM_ADAPTIVE: u8 = 2;
+
+/// Bitwise context mixing over all models
+pub const CODEC_MIXER: u8 = 0;
+/// PPM blended with the LZP match, coded a byte at a time: much faster, weaker
+pub const CODEC_PPM: u8 = 1;
+
+/// Learning-rate schedule: the fixed V8/V9 rates
+pub const LR_FIXED: u8 = 0;
+/// Rates starting high and decaying towards a floor as bits are coded
+pub const LR_DECAY: u8 = 1;
+/// Each weight's step divided by the running RMS of its gradient
+pub const LR_RMS: u8 = 2;
+/// Rates following the recent coding error relative to its long-run level
+pub const LR_ERROR: u8 = 3;
+
+/// Arithmetic coder shifting out one bit at a time, with pending bits for
+/// carries (V7–V9)
+pub const CODER_BITS: u8 = 0;
+/// Carry-less 32-bit binary coder shifting whole bytes in and out; PPM
+/// codes each byte as eight decisions down its bit tree
+pub const CODER_BYTES: u8 = 1;
+
+/// Indirect model: byte histories of order-1/order-2 contexts as contexts
+pub const MODEL_INDIRECT: u8 = 1;
+/// Word model: previous words with the current prefix, position in word
+pub const MODEL_WORDS: u8 = 2;
+/// Column model: field index, position in line and the byte one row up
+pub const MODEL_COLUMNS: u8 = 4;
+/// Markup model: tag/attribute state, open element, Markdown block kind
+pub const MODEL_MARKUP: u8 = 8;
+/// Code model: indentation, bracket nesting, string/comment state, identifiers
+pub const MODEL_CODE: u8 = 16;
+pub const MODEL_ALL: u8 =
+    MODEL_INDIRECT | MODEL_WORDS | MODEL_COLUMNS | MODEL_MARKUP | MODEL_CODE;
+
+impl ModelConfig {
+    /// The fixed model used by the V8 and V9 formats.
+    pub fn legacy() -> Self {
+        Self {
+            max_order: 6,
+            selectors: 0,
+            apm: 0,
+            bit_table: TABLE_COUNTS,
+            models: 0,
+            orders: Vec::new(),
+            ppm_orders: Vec::new(),
+            matches: Vec::new(),
+            match_table_bits: 20,
+            lzp_window: 0,
+            lzp_table: 0,
+            lzp_chain: 0,
+            history_window: 0,
+            ppm_budget: 0,
+            ppm_policy: PPM_PRUNE,
+            ppm_model: PPM_INTERPOLATED,
+            codec: CODEC_MIXER,
+            ppm_halve: 0,
+            ppm_tree: 0,
+            lr_schedule: LR_FIXED,
+            fixed_point: 0,
+            coder: CODER_BITS,
+            prob_bits: MIN_PROB_BITS,
+            bit_table_bits: 24,
+            context_hash: HASH_FNV,
+            residual_nn: 1,
+            level: 0,
+        }
+    }
+
+    /// Configuration of compression level `level` (1–9). Level 1 mixes two
+    /// order models, a match model and low-order PPM with a single weight
+    /// set; level 2 adds orders and an APM stage, and each level up adds
+    /// models, selectors, stages and memory; levels 7–9 also normalize the
+    /// learning rates. Level 6 is the default.
+    pub fn for_level(level: u8) -> Result<Self, String> {
+        let full = Self::default();
+        let cfg = match level {
+            1 => Self {
+                selectors: 0,
+                apm: APM_FINAL,
+                models: 0,
+                orders: vec![1, 3],
+                ppm_orders: vec![0, 1, 2],
+                ppm_model: PPM_INTERPOLATED,
+                matches: vec![24],
+                match_table_bits: 20,
+                lzp_window: 22,
+                lzp_table: 20,
+                history_window: 22,
+                ppm_budget: 24,
+                bit_table_bits: 22,
+                ..full
+            },
+            2 => Self {
+                selectors: 0,
+                apm: APM_ORDER1 | APM_FINAL,
+                models: 0,
+                orders: vec![0, 1, 2, 4, 6],
+                ppm_orders: vec![0, 1, 2, 3, 4],
+                matches: vec![24],
+                lzp_window: 22,
+                lzp_table: 20,
+                history_window: 22,
+                ppm_budget: 24,
+                bit_table_bits: 22,
+                ..full
+            },
+            3 => Self {
+                selectors: SEL_CLASS,
+                apm: APM_ORDER1 | APM_MATCH | APM_FINAL,
+                models: 0,
+                orders: vec![0, 1, 2, 4, 8],
+                ppm_orders: vec![0, 1, 2, 3, 4],
+                matches: vec![32],
+                lzp_window: 20,
+                lzp_table: 20,
+                history_window: 22,
+                ppm_budget: 25,
+                bit_table_bits: 21,
+                ..full
+            },
+            4 => Self {
+                selectors: SEL_CLASS | SEL_MATCH,
+                models: MODEL_WORDS | MODEL_INDIRECT,
+                matches: vec![32, 12 | MATCH_LONGEST],
+                lzp_window: 21,
+                lzp_table: 21,
+                history_window: 22,
+                ppm_budget: 26,
+                bit_table_bits: 22,
+                ..full
+            },
+            5 => Self {
+                selectors: SEL_CLASS | SEL_MATCH | SEL_ORDER1,
+                models: MODEL_WORDS | MODEL_INDIRECT | MODEL_COLUMNS | MODEL_MARKUP,
+                ppm_budget: 27,
+                bit_table_bits: 23,
+                ..full
+            },
+            6 => full,
+            7 => Self {
+                ppm_orders: (0..=8).collect(),
+                lr_schedule: LR_RMS,
+                lzp_window: 24,
+                lzp_table: 23,
+                history_window: 26,
+                ppm_budget: 29,
+                bit_table_bits: 25,
+                ..full
+            },
+            8 => Self {
+                ppm_orders: (0..=10).collect(),
+                lr_schedule: LR_RMS,
+                matches: vec![32, 16 | MATCH_LONGEST, 8],
+                lzp_window: 26,
+                lzp_table: 24,
+                lzp_chain: 8,
+                history_window: 28,
+                ppm_budget: 30,
+                bit_table_bits: 26,
+                ..full
+            },
+            9 => Self {
+                ppm_orders: (0..=12).collect(),
+                lr_schedule: LR_RMS,
+                matches: vec![32, 20 | MATCH_LONGEST, 12, 6 | MATCH_LONGEST],
+                lzp_window: 28,
+                lzp_table: 25,
+                lzp_chain: 16,
+                history_window: 30,
+                ppm_budget: 31,
+                bit_table_bits: 27,
+                ..full
+            },
+            _ => return Err(format!("Unknown compression level {level} (levels are {MIN_LEVEL}-{MAX_LEVEL})")),
+        };
+        Ok(Self { level, ..cfg })
+    }
+
+    /// Orders of the hashed order models.
+    pub fn effective_orders(&self) -> Vec<u8> {
+        if self.orders.is_empty() {
+            (0..=self.max_order).collect()
+        } else {
+            self.orders.clone()
+        }
+    }
+
+    /// Orders PPM interpolates over.
+    pub fn effective_ppm_orders(&self) -> Vec<u8> {
+        if self.ppm_orders.is_empty() {
+            (0..=self.max_order).collect()
+        } else {
+            self.ppm_orders.clone()
+        }
+    }
+
+    /// This configuration with its history, LZP, match and bit tables shrunk
+    /// to what `len` bytes of input can fill: windows and the LZP table to
+    /// the next power of two, the match and bit tables to
+    /// `2^MATCH_TABLE_ENTRIES_PER_INPUT_BITS` and
+    /// `2^BIT_TABLE_BYTES_PER_INPUT_BITS` times that, each within the sizes
+    /// `validate` accepts.
+    pub fn fitted_to(&self, len: usize) -> Self {
+        let bits = len.max(1).next_power_of_two().trailing_zeros() as u8;
+        let mut cfg = self.clone();
+        if cfg.history_window != 0 {
+            cfg.history_window = cfg.history_window.min(bits.max(16));
+        }
+        if cfg.lzp_window != 0 {
+            cfg.lzp_window = cfg.lzp_window.min(bits.max(10));
+            cfg.lzp_table = cfg.lzp_table.min(bits.max(10));
+        }
+        let match_bits = (bits + MATCH_TABLE_ENTRIES_PER_INPUT_BITS).max(MIN_MATCH_TABLE_BITS);
+        cfg.match_table_bits = cfg.match_table_bits.min(match_bits);
+        let table_bits = (bits + BIT_TABLE_BYTES_PER_INPUT_BITS).max(MIN_BIT_TABLE_BITS);
+        cfg.bit_table_bits = cfg.bit_table_bits.min(table_bits);
+        cfg
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.max_order, self.selectors, self.apm, self.bit_table, self.models];
+        for list in [&self.orders, &self.ppm_orders, &self.matches] {
+            out.push(list.len() as u8);
+            out.extend_from_slice(list);
+        }
+        out.push(self.match_table_bits);
+        out.extend_from_slice(&[self.lzp_window, self.lzp_table, self.lzp_chain]);
+        out.extend_from_slice(&[self.history_window, self.ppm_budget, self.ppm_policy]);
+        out.extend_from_slice(&[self.ppm_model, self.codec, self.ppm_halve, self.ppm_tree]);
+        out.extend_from_slice(&[self.lr_schedule, self.fixed_point, self.coder, self.prob_bits]);
+        out.extend_from_slice(&[self.bit_table_bits, self.context_hash, self.residual_nn, self.level]);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut r = ConfigReader(data);
+        let cfg = Self {
+            max_order: r.byte()?,
+            selectors: r.byte()?,
+            apm: r.byte()?,
+            bit_table: r.byte()?,
+            models: r.byte()?,
+            orders: r.list()?,
+            ppm_orders: r.list()?,
+            matches: r.list()?,
+            match_table_bits: r.byte()?,
+            lzp_window: r.byte()?,
+            lzp_table: r.byte()?,
+            lzp_chain: r.byte()?,
+            history_window: r.byte()?,
+            ppm_budget: r.byte()?,
+            ppm_policy: r.byte()?,
+            ppm_model: r.byte()?,
+            codec: r.byte()?,
+            ppm_halve: r.byte()?,
+            ppm_tree: r.byte()?,
+            lr_schedule: r.byte()?,
+            fixed_point: r.byte()?,
+            coder: r.byte()?,
+            prob_bits: r.byte()?,
+            bit_table_bits: r.byte()?,
+            context_hash: r.byte()?,
+            residual_nn: r.byte()?,
+            level: r.byte()?,
+        };
+        if !r.0.is_empty() {
+            return Err(format!("{} trailing bytes after the model config", r.0.len()));
+        }
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_order > MAX_CONTEXT_ORDER {
+            return Err(format!("Unsupported max order {}", self.max_order));
+        }
+        for (name, list, cap) in [
+            ("context", &self.effective_orders(), MAX_ORDER_MODELS),
+            ("PPM", &self.effective_ppm_orders(), MAX_PPM_ORDERS),
+        ] {
+            if list.len() > cap {
+                return Err(format!("Too many {name} orders ({})", list.len()));
+            }
+            if list.windows(2).any(|w| w[0] >= w[1]) || list.iter().any(|&o| o > MAX_CONTEXT_ORDER) {
+                return Err(format!("Invalid {name} order list {list:?}"));
+            }
+        }
+        if self.matches.len() > MAX_MATCH_MODELS {
+            return Err(format!("Too many match models ({})", self.matches.len()));
+        }
+        for &spec in &self.matches {
+            if !(2..=64).contains(&(spec & !MATCH_LONGEST)) {
+                return Err(format!("Invalid match model {spec:#x}"));
+            }
+        }
+        if !(MIN_MATCH_TABLE_BITS..=MAX_MATCH_TABLE_BITS).contains(&self.match_table_bits) {
+            return Err(format!("Invalid match table size 2^{}", self.match_table_bits));
+        }
+        if self.lzp_window != 0
+            && (!(10..=30).contains(&self.lzp_window)
+                || !(10..=30).contains(&self.lzp_table)
+                || !self.lzp_chain.is_power_of_two()
+                || self.lzp_chain > 16)
+        {
+            return Err(format!(
+                "Invalid LZP window/table/chain {}/{}/{}",
+                self.lzp_window, self.lzp_table, self.lzp_chain
+            ));
+        }
+        if self.history_window != 0
+            && (!(16..=32).contains(&self.history_window)
+                || self.lzp_window == 0
+                || self.lzp_window > self.history_window)
+        {
+            return Err(format!(
+                "History window {} cannot hold the LZP window {}",
+                self.history_window, self.lzp_window
+            ));
+        }
+        if self.ppm_budget != 0 && !(16..=40).contains(&self.ppm_budget) {
+            return Err(format!("Invalid PPM budget 2^{}", self.ppm_budget));
+        }
+        if self.ppm_policy > PPM_RESTART {
+            return Err(format!("Unknown PPM budget policy {}", self.ppm_policy));
+        }
+        if self.ppm_halve != 0 && !(2..=30).contains(&self.ppm_halve) {
+            return Err(format!("Invalid PPM halving threshold 2^{}", self.ppm_halve));
+        }
+        if self.ppm_tree > 1 {
+            return Err(format!("Invalid PPM tree flag {}", self.ppm_tree));
+        }
+        if self.ppm_model > PPM_ADAPTIVE {
+            return Err(format!("Unknown PPM model {}", self.ppm_model));
+        }
+        if self.codec > CODEC_PPM {
+            return Err(format!("Unknown codec {}", self.codec));
+        }
+        if self.lr_schedule > LR_ERROR {
+            return Err(format!("Unknown learning-rate schedule {}", self.lr_schedule));
+        }
+        if self.fixed_point > 1 {
+            return Err(format!("Invalid fixed-point flag {}", self.fixed_point));
+        }
+        if self.coder > CODER_BYTES {
+            return Err(format!("Unknown arithmetic coder {}", self.coder));
+        }
+        if !(MIN_PROB_BITS..=MAX_PROB_BITS).contains(&self.prob_bits) {
+            return Err(format!("Unsupported probability resolution of {} bits", self.prob_bits));
+        }
+        if !(MIN_BIT_TABLE_BITS..=MAX_BIT_TABLE_BITS).contains(&self.bit_table_bits) {
+            return Err(format!("Invalid bit table size 2^{}", self.bit_table_bits));
+        }
+        if self.context_hash > HASH_NEWEST_FIRST {
+            return Err(format!("Unknown context hash {}", self.context_hash));
+        }
+        if self.residual_nn > 1 {
+            return Err(format!("Invalid residual NN flag {}", self.residual_nn));
+        }
+        if self.level > MAX_LEVEL {
+            return Err(format!("Unknown compression level {}", self.level));
+        }
+        if self.selectors & !SEL_ALL != 0 {
+            return Err(format!("Unknown mixer selectors {:#x}", self.selectors));
+        }
+        if self.apm & !APM_ALL != 0 {
+            return Err(format!("Unknown APM stages {:#x}", self.apm));
+        }
+        if self.bit_table > TABLE_BUCKETED_ACTIVE {
+            return Err(format!("Unknown bit table kind {}", self.bit_table));
+        }
+        if self.models & !MODEL_ALL != 0 {
+            return Err(format!("Unknown context models {:#x}", self.models));
+        }
+        Ok(())
+    }
+}
+
+/// Cursor over a serialized config.
+struct ConfigReader<'a>(&'a [u8]);
+
+impl ConfigReader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let (&v, rest) = self.0.split_first().ok_or("Truncated model config")?;
+        self.0 = rest;
+        Ok(v)
+    }
+
+    /// A length byte followed by that many entries.
+    fn list(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.byte()? as usize;
+        if self.0.len() < len {
+            return Err("Truncated list in model config".into());
+        }
+        let (list, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(list.to_vec())
+    }
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            selectors: SEL_ALL,
+            apm: APM_ALL,
+            bit_table: TABLE_BUCKETED_ACTIVE,
+            models: MODEL_ALL,
+            orders: vec![0, 1, 2, 4, 8],
+            matches: vec![32, 12 | MATCH_LONGEST],
+            lzp_window: 22,
+            lzp_table: 22,
+            lzp_chain: 4,
+            history_window: 24,
+            ppm_budget: 28,
+            ppm_model: PPM_ESCAPE,
+            ppm_halve: 9,
+            ppm_tree: 1,
+            fixed_point: 1,
+            coder: CODER_BYTES,
+            prob_bits: MAX_PROB_BITS,
+            context_hash: HASH_NEWEST_FIRST,
+            residual_nn: 0,
+            level: DEFAULT_LEVEL,
+            ..Self::legacy()
+        }
+    }
+}
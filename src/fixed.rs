@@ -0,0 +1,753 @@
+use crate::apm::{APM_RATE, STAGE_LR};
+use crate::config::{LR_DECAY, LR_ERROR, LR_RMS};
+use crate::mixer::{
+    DECAY_END, DECAY_HALF, DECAY_START, ERR_FAST, ERR_MAX, ERR_MIN, ERR_SLOW, HIDDEN, LR,
+    MAX_MODELS, MAX_SETS, NN_LR, RMS_DECAY, RMS_EPS, RMS_GAIN, SET_LR, SSE_BINS, SSE_RATE,
+};
+use std::sync::OnceLock;
+
+// ── Number formats ──
+// Probabilities are fractions of 2^16 within 1..=P_MAX, logits are in 1/256
+// units within ±LOGIT_MAX (12.0), weights are in 1/65536 units and learning
+// rates fractions of 2^24. Every product fits an i64, including the RMS
+// steps' gradient times rate times gain.
+const P_SHIFT: u32 = 16;
+const P_ONE: i32 = 1 << P_SHIFT;
+const P_MAX: i32 = P_ONE - 1;
+const X_SHIFT: u32 = 8;
+const LOGIT_MAX: i32 = 12 << X_SHIFT;
+const W_SHIFT: u32 = 16;
+const RATE_SHIFT: u32 = 24;
+/// Weight rows are padded with zero weights and inputs to a multiple of
+/// this, the AVX2 kernels' width
+const ROW_ALIGN: usize = 8;
+const PADDED_MODELS: usize = MAX_MODELS.next_multiple_of(ROW_ALIGN);
+
+const fn rate(r: f64) -> i64 {
+    (r * (1u64 << RATE_SHIFT) as f64 + 0.5) as i64
+}
+
+const fn frac16(r: f64) -> i64 {
+    (r * 65536.0 + 0.5) as i64
+}
+
+const LR_Q: i64 = rate(LR);
+const SET_LR_Q: i64 = rate(SET_LR);
+const NN_LR_Q: i64 = rate(NN_LR);
+const SSE_RATE_Q: i64 = rate(SSE_RATE);
+const APM_RATE_Q: i64 = rate(APM_RATE);
+const STAGE_LR_Q: i64 = rate(STAGE_LR);
+
+const DECAY_START_Q: i64 = frac16(DECAY_START);
+const DECAY_END_Q: i64 = frac16(DECAY_END);
+const DECAY_HALF_Q: i64 = DECAY_HALF as i64;
+const ERR_FAST_SHIFT: u32 = ((1.0 / ERR_FAST) as u32).ilog2();
+const ERR_SLOW_SHIFT: u32 = ((1.0 / ERR_SLOW) as u32).ilog2();
+const ERR_MIN_Q: i64 = frac16(ERR_MIN);
+const ERR_MAX_Q: i64 = frac16(ERR_MAX);
+/// log2 of the updates a gradient moment averages over
+const RMS_SHIFT: u32 = ((1.0 / (1.0 - RMS_DECAY)) as u64).ilog2();
+const RMS_GAIN_Q: i64 = frac16(RMS_GAIN);
+
+/// squash at logits -12 + k/4, k = 0..=96, as fractions of 2^16
+const SQUASH_KNOTS: [i32; 97] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 3, 4, 5, 6, 8, 10, 13, 17, 22, 28, 36, 47, 60, 77, 98, 126, 162, 208,
+    267, 342, 439, 562, 720, 922, 1179, 1506, 1921, 2446, 3108, 3938, 4971, 6249, 7812, 9702,
+    11955, 14595, 17625, 21025, 24743, 28693, 32768, 36843, 40793, 44511, 47911, 50941, 53581,
+    55834, 57724, 59287, 60565, 61598, 62428, 63090, 63615, 64030, 64357, 64614, 64816, 64974,
+    65097, 65194, 65269, 65328, 65374, 65410, 65438, 65459, 65476, 65489, 65500, 65508, 65514,
+    65519, 65523, 65526, 65528, 65530, 65531, 65532, 65533, 65534, 65534, 65535, 65535, 65535,
+    65535, 65535, 65535,
+];
+
+/// Logit units between two knots
+const KNOT_STEP: i32 = 64;
+
+/// 1/(1 + e^-x) for a logit `x`, interpolated between the knots.
+#[inline(always)]
+pub(crate) fn squash(x: i32) -> i32 {
+    let x = x.clamp(-LOGIT_MAX, LOGIT_MAX - 1) + LOGIT_MAX;
+    let (k, w) = ((x / KNOT_STEP) as usize, x % KNOT_STEP);
+    let p = (SQUASH_KNOTS[k] * (KNOT_STEP - w) + SQUASH_KNOTS[k + 1] * w + KNOT_STEP / 2) / KNOT_STEP;
+    p.clamp(1, P_MAX)
+}
+
+static STRETCH: OnceLock<Vec<i16>> = OnceLock::new();
+
+/// ln(p / (1 - p)) for every probability: the smallest logit `squash` takes
+/// to reach it.
+fn stretch_table() -> &'static [i16] {
+    STRETCH.get_or_init(|| {
+        let mut t = vec![LOGIT_MAX as i16; P_ONE as usize];
+        let mut p = 0usize;
+        for x in -LOGIT_MAX..LOGIT_MAX {
+            let v = squash(x) as usize;
+            while p <= v {
+                t[p] = x as i16;
+                p += 1;
+            }
+        }
+        t
+    })
+}
+
+/// A model's probability in fixed point. `P_ONE` is a power of two, so the
+/// product is exact and truncating it has a single result on any platform.
+#[inline(always)]
+pub(crate) fn quantize(p: f64) -> i32 {
+    ((p * P_ONE as f64) as i32).clamp(1, P_MAX)
+}
+
+/// `v / 2^s` rounded to nearest, so small updates do not drift downwards.
+#[inline(always)]
+fn shr_round(v: i64, s: u32) -> i64 {
+    (v + (1 << (s - 1))) >> s
+}
+
+static INV_ROOT: OnceLock<Vec<u32>> = OnceLock::new();
+/// Fraction bits of the inverse roots
+const ROOT_SHIFT: u32 = 24;
+/// Low bits an RMS step drops from gradient times rate before scaling by
+/// the root
+const GRAD_DROP: u32 = 20;
+
+/// 2^24 / sqrt(m) for the mantissas m in 256..1024 `inv_root` normalizes to.
+fn inv_root_table() -> &'static [u32] {
+    INV_ROOT.get_or_init(|| {
+        (256u64..1024).map(|m| ((1u64 << (2 * ROOT_SHIFT)) / m).isqrt() as u32).collect()
+    })
+}
+
+/// 1/sqrt(v) as `r / 2^shift`, to about 0.2%. `v` must be at least 256.
+#[inline(always)]
+fn inv_root(table: &[u32], v: u64) -> (i64, u32) {
+    let e = (63 - v.leading_zeros()) & !1;
+    let m = (v >> (e - 8)) as usize;
+    (table[m - 256] as i64, ROOT_SHIFT + (e - 8) / 2)
+}
+
+/// RMS epsilon for gradients of inputs in units of 2^-`x_shift`.
+#[inline(always)]
+fn rms_eps(x_shift: u32) -> u64 {
+    (RMS_EPS * (1u64 << (P_SHIFT + x_shift)) as f64) as u64
+}
+
+/// `sum(w[i] * x[i])` over `w`.
+#[inline(always)]
+fn dot(w: &[i32], x: &[i32]) -> i64 {
+    #[cfg(target_arch = "x86_64")]
+    if w.len() >= avx2::LANES && std::is_x86_feature_detected!("avx2") {
+        return unsafe { avx2::dot(w, x) };
+    }
+    dot_scalar(w, x)
+}
+
+#[inline(always)]
+fn dot_scalar(w: &[i32], x: &[i32]) -> i64 {
+    w.iter().zip(x).map(|(&w, &x)| w as i64 * x as i64).sum()
+}
+
+/// Gradient step on a row of weights: `w[i] += rate * err * x[i]`, clamped
+/// to ±`limit`, with `err` a probability difference and `x[i]` in units of
+/// 2^-`x_shift`. With moments each step is instead divided by the running
+/// RMS of that weight's gradient, as in the floating-point mixer but with
+/// the epsilon inside the root.
+#[inline(always)]
+fn train(
+    w: &mut [i32],
+    x: &[i32],
+    x_shift: u32,
+    rate: i64,
+    err: i32,
+    limit: i32,
+    moments: Option<&mut [u64]>,
+) {
+    match moments {
+        None => {
+            let scaled = err as i64 * rate;
+            #[cfg(target_arch = "x86_64")]
+            if w.len() >= avx2::LANES && std::is_x86_feature_detected!("avx2") {
+                return unsafe { avx2::train(w, x, x_shift + RATE_SHIFT, scaled, limit) };
+            }
+            train_scalar(w, x, x_shift + RATE_SHIFT, scaled, limit);
+        }
+        Some(v) => {
+            let eps = rms_eps(x_shift);
+            let gain = rate * RMS_GAIN_Q;
+            let table = inv_root_table();
+            for i in 0..w.len() {
+                let g = err as i64 * x[i] as i64;
+                let g2 = g.unsigned_abs() * g.unsigned_abs();
+                v[i] = v[i] - (v[i] >> RMS_SHIFT) + (g2 >> RMS_SHIFT);
+                let (r, shift) = inv_root(table, v[i] + eps * eps);
+                // Gradient times rate and gain is below 2^62; dropping 20
+                // bits leaves room for the 20-bit root
+                let shift = shift + RATE_SHIFT - GRAD_DROP;
+                let step = shr_round(shr_round(g * gain, GRAD_DROP) * r, shift);
+                w[i] = (w[i] as i64 + step).clamp(-limit as i64, limit as i64) as i32;
+            }
+        }
+    }
+}
+
+/// `w[i] += scaled * x[i] / 2^shift`, rounded and clamped to ±`limit`.
+#[inline(always)]
+fn train_scalar(w: &mut [i32], x: &[i32], shift: u32, scaled: i64, limit: i32) {
+    for (w, &x) in w.iter_mut().zip(x) {
+        let step = shr_round(scaled * x as i64, shift);
+        *w = (*w as i64 + step).clamp(-limit as i64, limit as i64) as i32;
+    }
+}
+
+/// AVX2 versions of `dot` and the plain `train` step, chosen at run time.
+/// They compute exactly what the scalar loops do, so the output does not
+/// depend on the CPU.
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::{dot_scalar, train_scalar};
+    use std::arch::x86_64::*;
+
+    /// Weights per 256-bit step; shorter rows stay scalar
+    pub(super) const LANES: usize = super::ROW_ALIGN;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn dot(w: &[i32], x: &[i32]) -> i64 {
+        let x = &x[..w.len()];
+        let (wc, xc) = (w.chunks_exact(LANES), x.chunks_exact(LANES));
+        let tail = dot_scalar(wc.remainder(), xc.remainder());
+        let mut acc = _mm256_setzero_si256();
+        for (w, x) in wc.zip(xc) {
+            let a = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(x.as_ptr() as *const __m256i);
+            // Even and odd 32-bit lanes, each product sign-extended to 64 bits
+            acc = _mm256_add_epi64(acc, _mm256_mul_epi32(a, b));
+            let (a, b) = (_mm256_srli_epi64(a, 32), _mm256_srli_epi64(b, 32));
+            acc = _mm256_add_epi64(acc, _mm256_mul_epi32(a, b));
+        }
+        let mut sums = [0i64; 4];
+        _mm256_storeu_si256(sums.as_mut_ptr() as *mut __m256i, acc);
+        sums.iter().sum::<i64>() + tail
+    }
+
+    /// `train_scalar` eight weights at a time for the logit inputs' shift of
+    /// 32, where the rounded step is the high half of `scaled * x[i] +
+    /// 2^31`. The 64-bit product is `(hi * x[i] << 16) + lo * x[i]` with
+    /// `scaled` split at bit 16. With `|scaled| < 2^36` and logits below
+    /// 2^12 a step stays under 2^16, so the weights, which never leave
+    /// ±`limit`, add and clamp in 32 bits.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn train(w: &mut [i32], x: &[i32], shift: u32, scaled: i64, limit: i32) {
+        if shift != 32 || scaled.unsigned_abs() >= 1 << 36 {
+            return train_scalar(w, x, shift, scaled, limit);
+        }
+        let x = &x[..w.len()];
+        let hi = _mm256_set1_epi64x(scaled >> 16);
+        let lo = _mm256_set1_epi64x(scaled & 0xffff);
+        let round = _mm256_set1_epi64x(1 << 31);
+        let (max, min) = (_mm256_set1_epi32(limit), _mm256_set1_epi32(-limit));
+        let mut wc = w.chunks_exact_mut(LANES);
+        let mut xc = x.chunks_exact(LANES);
+        for (w, x) in wc.by_ref().zip(xc.by_ref()) {
+            let xv = _mm256_loadu_si256(x.as_ptr() as *const __m256i);
+            let wv = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            // Products of the even and the odd 32-bit lanes, plus 2^31
+            let product = |x| {
+                let v = _mm256_add_epi64(_mm256_slli_epi64(_mm256_mul_epi32(x, hi), 16), _mm256_mul_epi32(x, lo));
+                _mm256_add_epi64(v, round)
+            };
+            let (even, odd) = (product(xv), product(_mm256_srli_epi64(xv, 32)));
+            let step = _mm256_blend_epi32(_mm256_srli_epi64(even, 32), odd, 0b1010_1010);
+            let nw = _mm256_min_epi32(_mm256_max_epi32(_mm256_add_epi32(wv, step), min), max);
+            _mm256_storeu_si256(w.as_mut_ptr() as *mut __m256i, nw);
+        }
+        train_scalar(wc.into_remainder(), xc.remainder(), shift, scaled, limit);
+    }
+}
+
+/// Adaptive probability map over fixed-point probabilities, laid out like
+/// `Apm`: 33 bins per context spanning logits ±8.
+#[derive(Clone)]
+struct FixedApm {
+    t: Vec<u16>,
+    n_ctx: usize,
+}
+
+const APM_BINS: usize = 33;
+/// Logit units per bin (0.5)
+const APM_STEP: i32 = 128;
+const APM_RANGE: i32 = 16 * APM_STEP / 2;
+
+impl FixedApm {
+    fn new(n_ctx: usize) -> Self {
+        let row: Vec<u16> = (0..APM_BINS as i32)
+            .map(|i| squash(i * APM_STEP - APM_RANGE) as u16)
+            .collect();
+        Self { t: row.repeat(n_ctx), n_ctx }
+    }
+
+    /// Refined probability for the logit `x` in context `ctx`, with the
+    /// slot and share of the upper bin for the update.
+    #[inline(always)]
+    fn refine(&self, x: i32, ctx: usize) -> (i32, (usize, i32)) {
+        let pos = x.clamp(-APM_RANGE, APM_RANGE - 1) + APM_RANGE;
+        let (bin, frac) = ((pos / APM_STEP) as usize, pos % APM_STEP);
+        let idx = (ctx % self.n_ctx) * APM_BINS + bin;
+        let p = (self.t[idx] as i32 * (APM_STEP - frac) + self.t[idx + 1] as i32 * frac) / APM_STEP;
+        (p.clamp(1, P_MAX), (idx, frac))
+    }
+
+    #[inline(always)]
+    fn update(&mut self, (idx, frac): (usize, i32), bit: u8) {
+        let target = if bit != 0 { P_MAX } else { 0 } as i64;
+        for (i, share) in [(idx, APM_STEP - frac), (idx + 1, frac)] {
+            let v = self.t[i] as i64;
+            let step = (target - v) * share as i64 * APM_RATE_Q / APM_STEP as i64;
+            self.t[i] = (v + shr_round(step, RATE_SHIFT)).clamp(0, P_MAX as i64) as u16;
+        }
+    }
+}
+
+/// squash at logits -12..=12 as fractions of 2^32, for the final stage
+const FINE_KNOTS: [u32; 25] = [
+    26389, 71732, 194982, 529976, 1440318, 3912935, 10619836, 28745576, 77250184, 203692574,
+    511972652, 1155094609, 2147483648, 3139872687, 3782994644, 4091274722, 4217717112,
+    4266221720, 4284347460, 4291054361, 4293526978, 4294437320, 4294772314, 4294895564,
+    4294940907,
+];
+
+/// Final-stage bins span logits ±12 a logit apart
+const FINAL_BINS: usize = 25;
+const FINAL_STEP: i32 = 1 << X_SHIFT;
+/// Final-stage contexts: log2 buckets of the confident run, per bit_pos
+const FINAL_RUNS: usize = 16;
+/// Updates a final-stage bin's starting value counts as, and the most its
+/// rate averages over
+const FINAL_PRIOR: u16 = 100;
+const FINAL_LIMIT: u16 = 1023;
+
+/// Last stage before the coder: an APM with 32-bit bins that turns a 16-bit
+/// probability into one of up to `MAX_PROB_BITS` bits. It is keyed by how
+/// many bits in a row were coded at 15/16 or better, so long stretches the
+/// model gets right can learn probabilities far closer to 0 and 1 than the
+/// 16-bit stages before it hold. Each bin adapts at 1/(n + 1.5) after `n`
+/// updates, counting the starting value as `FINAL_PRIOR` of them, down to
+/// 1/`FINAL_LIMIT`, so rare misses are weighed properly.
+#[derive(Clone)]
+pub(crate) struct FinalApm {
+    stretch: &'static [i16],
+    t: Vec<u32>,
+    n: Vec<u16>,
+    bits: u32,
+    run: u32,
+    /// Slot and upper-bin share of the last `refine`, and its output
+    last: (usize, i32, u64),
+}
+
+impl FinalApm {
+    /// Stage producing probabilities as fractions of 2^`bits`.
+    pub(crate) fn new(bits: u8) -> Self {
+        Self {
+            stretch: stretch_table(),
+            t: FINE_KNOTS.repeat(FINAL_RUNS * 8),
+            n: vec![FINAL_PRIOR; FINAL_RUNS * 8 * FINAL_BINS],
+            bits: bits as u32,
+            run: 0,
+            last: (0, 0, 0),
+        }
+    }
+
+    /// Coder probability of a 1 bit for `p`, a fraction of 2^16.
+    #[inline(always)]
+    pub(crate) fn refine(&mut self, p: i32, bit_pos: usize) -> u64 {
+        let x = self.stretch[p.clamp(1, P_MAX) as usize] as i32;
+        let pos = x.clamp(-LOGIT_MAX, LOGIT_MAX - 1) + LOGIT_MAX;
+        let (bin, frac) = ((pos / FINAL_STEP) as usize, pos % FINAL_STEP);
+        let ctx = (self.run + 1).ilog2().min(FINAL_RUNS as u32 - 1) as usize * 8 + bit_pos;
+        let idx = ctx * FINAL_BINS + bin;
+        let q = (self.t[idx] as u64 * (FINAL_STEP - frac) as u64 + self.t[idx + 1] as u64 * frac as u64)
+            / FINAL_STEP as u64;
+        let p1 = (q >> (32 - self.bits)).clamp(1, (1 << self.bits) - 1);
+        self.last = (idx, frac, p1);
+        p1
+    }
+
+    #[inline(always)]
+    pub(crate) fn update(&mut self, bit: u8) {
+        let (idx, frac, p1) = self.last;
+        let target = if bit != 0 { u32::MAX } else { 0 } as i64;
+        for (i, share) in [(idx, FINAL_STEP - frac), (idx + 1, frac)] {
+            if share == 0 {
+                continue;
+            }
+            let (v, n) = (self.t[i] as i64, self.n[i] as i64);
+            let step = (target - v) * share as i64 * 2 / ((2 * n + 3) * FINAL_STEP as i64);
+            self.t[i] = (v + step).clamp(0, u32::MAX as i64) as u32;
+            if self.n[i] < FINAL_LIMIT {
+                self.n[i] += 1;
+            }
+        }
+        let scale = 1u64 << self.bits;
+        let coded = if bit != 0 { p1 } else { scale - p1 };
+        self.run = if coded >= scale - scale / 16 { self.run.saturating_add(1) } else { 0 };
+    }
+}
+
+/// Running mean squared gradient of every weight, for RMS-normalized rates.
+#[derive(Clone)]
+struct FixedMoments {
+    sets: Vec<Vec<u64>>,
+    final_w: [[u64; MAX_SETS]; 8],
+    nn_w1: Vec<u64>,
+    nn_b1: [[u64; HIDDEN]; 8],
+    nn_w2: [[u64; HIDDEN]; 8],
+    nn_b2: [u64; 8],
+}
+
+/// What the last prediction computed, kept for its update.
+#[derive(Clone)]
+struct FixedBit {
+    bit_pos: usize,
+    rows: [usize; MAX_SETS],
+    x: [i32; PADDED_MODELS],
+    dots: [i32; MAX_SETS],
+    hidden: [i32; HIDDEN],
+    mixed: i32,
+    sse_pos: i32,
+    apm_slots: Vec<(usize, i32)>,
+    stage_x: [i32; 8],
+    final_p: i32,
+}
+
+/// The mixing back end of `ContextMixer` in integer arithmetic: stretch and
+/// squash, weight sets and layer 2, the residual NN, SSE, APM chain and
+/// stage mixer, with the same structure, rates and schedules as the
+/// floating-point path.
+///
+/// Model probabilities are quantized to 16 bits on entry and everything
+/// after that is exact integer arithmetic, with squash interpolated between
+/// fixed knots instead of tables built with `exp` and `ln`. A file coded
+/// with it decodes bit-exactly on any platform and build.
+///
+/// The models still hand over `f64`s, but each one comes out of a fixed
+/// sequence of `+`, `-`, `*`, `/` and `sqrt` on binary64 values: count
+/// ratios in the bit tables, `(v + 0.5) / 2^22` in the state maps, the hit
+/// averages of the match models, and the PPM terms summed up the bit tree
+/// and divided once per bit. IEEE 754 rounds each of those operations
+/// correctly, Rust never fuses them into `mul_add`, its tier-1 targets do
+/// not keep x87 extended precision in between, and no `exp`, `ln` or
+/// `powf` feeds any model, so every such platform computes the same bits
+/// before `quantize`.
+#[derive(Clone)]
+pub(crate) struct FixedMix {
+    n_in: usize,
+    /// Row length: `n_in` padded to a multiple of `ROW_ALIGN`
+    stride: usize,
+    stretch: &'static [i16],
+    /// Layer-1 weights, `stride` per row
+    sets: Vec<Vec<i32>>,
+    final_w: [[i32; MAX_SETS]; 8],
+    /// Hidden-unit weights, `stride` per (bit_pos, unit)
+    nn_w1: Vec<i32>,
+    nn_b1: [[i32; HIDDEN]; 8],
+    nn_w2: [[i32; HIDDEN]; 8],
+    nn_b2: [i32; 8],
+    /// Whether the NN adds its correction
+    residual: bool,
+    /// SSE bins as fractions of 2^28
+    sse: [[i32; SSE_BINS]; 8],
+    apms: Vec<FixedApm>,
+    /// Stage-mixer weights per bit_pos; `None` keeps the fixed 0.7/0.3 blend
+    stage: Option<[[i32; 8]; 8]>,
+    schedule: u8,
+    trained: i64,
+    /// Fast and slow averages of the squared error per bit_pos
+    err_avg: [(i64, i64); 8],
+    moments: Option<Box<FixedMoments>>,
+    last: FixedBit,
+}
+
+impl FixedMix {
+    /// Back end for `n_in` inputs with weight sets of the given row counts,
+    /// APM stages of the given context counts, with or without the residual
+    /// NN, starting from the float mixer's initial NN weights.
+    pub(crate) fn new(
+        n_in: usize,
+        set_rows: &[usize],
+        apm_contexts: &[usize],
+        schedule: u8,
+        residual: bool,
+        nn_w1: &[[[f64; HIDDEN]; MAX_MODELS]; 8],
+        nn_b1: &[[f64; HIDDEN]; 8],
+    ) -> Self {
+        let one = 1i32 << W_SHIFT;
+        let stride = n_in.next_multiple_of(ROW_ALIGN);
+        let q = |v: f64| (v * one as f64).round() as i32;
+        let sets: Vec<Vec<i32>> = set_rows
+            .iter()
+            .map(|&rows| {
+                let mut w = vec![0i32; rows * stride];
+                for row in w.chunks_exact_mut(stride) {
+                    row[0] = one;
+                }
+                w
+            })
+            .collect();
+        let mut w1 = Vec::with_capacity(8 * HIDDEN * stride);
+        for bp in nn_w1 {
+            for j in 0..HIDDEN {
+                w1.extend(bp[..n_in].iter().map(|unit| q(unit[j])));
+                w1.resize(w1.len() + stride - n_in, 0);
+            }
+        }
+        let n_stages = 2 + apm_contexts.len();
+        let mut sse = [[0i32; SSE_BINS]; 8];
+        for row in sse.iter_mut() {
+            for (bin, v) in row.iter_mut().enumerate() {
+                *v = ((2 * bin + 1) << 21) as i32;
+            }
+        }
+        Self {
+            n_in,
+            stride,
+            stretch: stretch_table(),
+            moments: (schedule == LR_RMS).then(|| {
+                Box::new(FixedMoments {
+                    sets: sets.iter().map(|w| vec![0; w.len()]).collect(),
+                    final_w: [[0; MAX_SETS]; 8],
+                    nn_w1: vec![0; w1.len()],
+                    nn_b1: [[0; HIDDEN]; 8],
+                    nn_w2: [[0; HIDDEN]; 8],
+                    nn_b2: [0; 8],
+                })
+            }),
+            final_w: [[one / set_rows.len() as i32; MAX_SETS]; 8],
+            sets,
+            nn_w1: w1,
+            nn_b1: nn_b1.map(|row| row.map(q)),
+            nn_w2: [[q(0.15); HIDDEN]; 8],
+            nn_b2: [0; 8],
+            residual,
+            sse,
+            apms: apm_contexts.iter().map(|&n| FixedApm::new(n)).collect(),
+            stage: (!apm_contexts.is_empty()).then(|| [[one / n_stages as i32; 8]; 8]),
+            schedule,
+            trained: 0,
+            err_avg: [(1 << 30, 1 << 30); 8],
+            last: FixedBit {
+                bit_pos: 0,
+                rows: [0; MAX_SETS],
+                x: [0; PADDED_MODELS],
+                dots: [0; MAX_SETS],
+                hidden: [0; HIDDEN],
+                mixed: 0,
+                sse_pos: 0,
+                apm_slots: vec![(0, 0); apm_contexts.len()],
+                stage_x: [0; 8],
+                final_p: 0,
+            },
+        }
+    }
+
+    #[inline(always)]
+    fn stretch(&self, p: i32) -> i32 {
+        unsafe { *self.stretch.get_unchecked(p as usize) as i32 }
+    }
+
+    /// Probability of a 1 bit as a fraction of 2^16, from the models'
+    /// predictions, the row of each weight set and each APM's context.
+    #[inline(always)]
+    pub(crate) fn predict(&mut self, bit_pos: usize, preds: &[f64], rows: &[usize; MAX_SETS], apm_ctx: &[usize]) -> i32 {
+        let n = self.stride;
+        let mut x = [0i32; PADDED_MODELS];
+        for (x, &p) in x.iter_mut().zip(&preds[..self.n_in]) {
+            *x = self.stretch(quantize(p));
+        }
+
+        let mut dots = [0i32; MAX_SETS];
+        for (k, set) in self.sets.iter().enumerate() {
+            let dot = dot(&set[rows[k] * n..(rows[k] + 1) * n], &x[..n]);
+            dots[k] = (dot >> W_SHIFT) as i32;
+        }
+        let linear = if self.sets.len() == 1 {
+            dots[0]
+        } else {
+            let mut dot = 0i64;
+            for (&w, &d) in self.final_w[bit_pos][..self.sets.len()].iter().zip(&dots) {
+                dot += w as i64 * d.clamp(-LOGIT_MAX, LOGIT_MAX) as i64;
+            }
+            (dot >> W_SHIFT) as i32
+        };
+
+        let mut hidden = [0i32; HIDDEN];
+        let mut correction = 0i64;
+        if self.residual {
+            let units = self.nn_w1[bit_pos * HIDDEN * n..].chunks_exact(n);
+            for ((h, w), &b) in hidden.iter_mut().zip(units).zip(&self.nn_b1[bit_pos]) {
+                let sum = ((b as i64) << X_SHIFT) + dot(w, &x[..n]);
+                *h = squash((sum >> W_SHIFT) as i32);
+            }
+            for (&w, &h) in self.nn_w2[bit_pos].iter().zip(&hidden) {
+                correction += w as i64 * h as i64;
+            }
+            correction = (self.nn_b2[bit_pos] as i64 + (correction >> P_SHIFT)) >> (W_SHIFT - X_SHIFT);
+        }
+        let mixed = squash(linear.saturating_add(correction as i32));
+
+        // SSE: interpolate between the two bins around the mixer output
+        let sse_pos = mixed * (SSE_BINS - 1) as i32;
+        let (bin, frac) = ((sse_pos >> P_SHIFT) as usize, (sse_pos & P_MAX) as i64);
+        let row = &self.sse[bit_pos];
+        let sse_p = (row[bin] as i64 * (P_ONE as i64 - frac) + row[bin + 1] as i64 * frac) >> (P_SHIFT + 12);
+        let sse_p = (sse_p as i32).clamp(1, P_MAX);
+
+        let mut stage_x = [0i32; 8];
+        let final_p = match &self.stage {
+            None => (7 * mixed + 3 * sse_p + 5) / 10,
+            Some(w) => {
+                stage_x[0] = self.stretch(mixed);
+                stage_x[1] = self.stretch(sse_p);
+                let mut p = sse_p;
+                for (k, apm) in self.apms.iter().enumerate() {
+                    let (q, slot) = apm.refine(self.stretch(p), apm_ctx[k]);
+                    self.last.apm_slots[k] = slot;
+                    stage_x[2 + k] = self.stretch(q);
+                    p = q;
+                }
+                let mut dot = 0i64;
+                for i in 0..2 + self.apms.len() {
+                    dot += w[bit_pos][i] as i64 * stage_x[i] as i64;
+                }
+                squash((dot >> W_SHIFT) as i32)
+            }
+        };
+
+        let last = &mut self.last;
+        last.bit_pos = bit_pos;
+        last.rows = *rows;
+        last.x = x;
+        last.dots = dots;
+        last.hidden = hidden;
+        last.mixed = mixed;
+        last.sse_pos = sse_pos;
+        last.stage_x = stage_x;
+        last.final_p = final_p;
+        final_p
+    }
+
+    /// Scale of the learning rates as a fraction of 2^16, as in
+    /// `ContextMixer::rate_scale`.
+    #[inline(always)]
+    fn rate_scale(&self, bit_pos: usize) -> i64 {
+        match self.schedule {
+            LR_DECAY => {
+                DECAY_END_Q + (DECAY_START_Q - DECAY_END_Q) * DECAY_HALF_Q / (DECAY_HALF_Q + self.trained)
+            }
+            LR_ERROR => {
+                let (fast, slow) = self.err_avg[bit_pos];
+                ((fast << 16) / slow.max(1)).clamp(ERR_MIN_Q, ERR_MAX_Q)
+            }
+            _ => 1 << 16,
+        }
+    }
+
+    /// Train everything on the bit just coded after the last `predict`.
+    #[inline(always)]
+    pub(crate) fn update(&mut self, bit: u8) {
+        let n = self.stride;
+        let last = &self.last;
+        let bp = last.bit_pos;
+        let target = (bit as i32) << P_SHIFT;
+        let err = target - last.mixed;
+        let scale = self.rate_scale(bp);
+        let (lr, set_lr, nn_lr) = ((LR_Q * scale) >> 16, (SET_LR_Q * scale) >> 16, (NN_LR_Q * scale) >> 16);
+        let mut moments = self.moments.as_deref_mut();
+        let limit8 = 8 << W_SHIFT;
+        let limit4 = 4 << W_SHIFT;
+
+        if self.sets.len() == 1 {
+            let row = last.rows[0] * n..(last.rows[0] + 1) * n;
+            let v = moments.as_mut().map(|m| &mut m.sets[0][row.clone()]);
+            train(&mut self.sets[0][row], &last.x[..n], X_SHIFT, lr, err, limit8, v);
+        } else {
+            for (k, set) in self.sets.iter_mut().enumerate() {
+                let set_err = target - squash(last.dots[k]);
+                let row = last.rows[k] * n..(last.rows[k] + 1) * n;
+                let v = moments.as_mut().map(|m| &mut m.sets[k][row.clone()]);
+                train(&mut set[row], &last.x[..n], X_SHIFT, set_lr, set_err, limit8, v);
+            }
+            let n_sets = self.sets.len();
+            let x = last.dots.map(|dot| dot.clamp(-LOGIT_MAX, LOGIT_MAX));
+            let v = moments.as_mut().map(|m| &mut m.final_w[bp][..n_sets]);
+            train(&mut self.final_w[bp][..n_sets], &x[..n_sets], X_SHIFT, lr, err, limit8, v);
+        }
+
+        if self.residual {
+            let v = moments.as_mut().map(|m| &mut m.nn_w2[bp][..]);
+            train(&mut self.nn_w2[bp], &last.hidden, P_SHIFT, nn_lr, err, limit4, v);
+            let v = moments.as_mut().map(|m| std::slice::from_mut(&mut m.nn_b2[bp]));
+            train(std::slice::from_mut(&mut self.nn_b2[bp]), &[1], 0, nn_lr, err, limit4, v);
+
+            for j in 0..HIDDEN {
+                let h = last.hidden[j] as i64;
+                let d = (err as i64 * self.nn_w2[bp][j] as i64) >> W_SHIFT;
+                let d = (((d * h) >> P_SHIFT) * (P_ONE as i64 - h)) >> P_SHIFT;
+                let row = (bp * HIDDEN + j) * n..(bp * HIDDEN + j + 1) * n;
+                let v = moments.as_mut().map(|m| &mut m.nn_w1[row.clone()]);
+                train(&mut self.nn_w1[row], &last.x[..n], X_SHIFT, nn_lr, d as i32, limit4, v);
+                let v = moments.as_mut().map(|m| std::slice::from_mut(&mut m.nn_b1[bp][j]));
+                train(std::slice::from_mut(&mut self.nn_b1[bp][j]), &[1], 0, nn_lr, d as i32, limit4, v);
+            }
+        }
+
+        let bin = (last.sse_pos >> P_SHIFT) as usize;
+        let target28 = (bit as i64) << 28;
+        for v in &mut self.sse[bp][bin..bin + 2] {
+            *v += shr_round((target28 - *v as i64) * SSE_RATE_Q, RATE_SHIFT) as i32;
+        }
+
+        if let Some(w) = &mut self.stage {
+            for (k, apm) in self.apms.iter_mut().enumerate() {
+                apm.update(last.apm_slots[k], bit);
+            }
+            let stage_err = target - last.final_p;
+            let n_stages = 2 + self.apms.len();
+            train(&mut w[bp][..n_stages], &last.stage_x[..n_stages], X_SHIFT, STAGE_LR_Q, stage_err, limit4, None);
+        }
+
+        self.trained += 1;
+        let e2 = err as i64 * err as i64;
+        let (fast, slow) = &mut self.err_avg[bp];
+        *fast += (e2 - *fast) >> ERR_FAST_SHIFT;
+        *slow += (e2 - *slow) >> ERR_SLOW_SHIFT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_kernels_match_scalar() {
+        if !std::is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut state = 0x9e37_79b9u32;
+        let mut next = |range: i32| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as i32 % (2 * range + 1) - range
+        };
+        let limit = 8 << W_SHIFT;
+        for len in 0..40 {
+            let w: Vec<i32> = (0..len).map(|_| next(limit)).collect();
+            let x: Vec<i32> = (0..len).map(|_| next(LOGIT_MAX)).collect();
+            assert_eq!(unsafe { avx2::dot(&w, &x) }, dot_scalar(&w, &x));
+            for scaled in [0, 1, -1, 335544 << 16, -(503316 << 16), next(1 << 23) as i64 * 4099] {
+                let (mut a, mut b) = (w.clone(), w.clone());
+                unsafe { avx2::train(&mut a, &x, X_SHIFT + RATE_SHIFT, scaled, limit) };
+                train_scalar(&mut b, &x, X_SHIFT + RATE_SHIFT, scaled, limit);
+                assert_eq!(a, b, "len {len} scaled {scaled}");
+            }
+        }
+    }
+}
@@ -2,8 +2,8 @@
 #[inline]
 pub fn fnv(d: &[u8], s: usize, e: usize) -> u32 {
     let mut h: u32 = 2166136261;
-    for i in s..e {
-        h = (h ^ d[i] as u32).wrapping_mul(16777619);
+    for &b in &d[s..e] {
+        h = (h ^ b as u32).wrapping_mul(16777619);
     }
     h
 }
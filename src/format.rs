@@ -2,6 +2,8 @@ pub const MAGIC: &[u8; 4] = b"QICM";
 pub const FMT_V7: u16 = 7;
 pub const FMT_V8: u16 = 8;
 pub const FMT_V9: u16 = 9;
+/// Frozen like V7–V9; later changes to the config or the models bump it
+pub const FMT_V10: u16 = 10;
 /// Header: 4 bytes magic + 2 bytes version (LE) + 4 bytes preprocessed length (LE) = 10 bytes
 pub const HEADER_SIZE: usize = 10;
 
@@ -55,6 +57,65 @@ pub fn header_size_v9(num_blocks: usize) -> usize {
     12 + 8 * num_blocks
 }
 
+/// V10 header: magic(4) + version(2) + total_preproc_len(4) + config_len(2) + config
+/// + num_blocks(2) + per-block (preproc_len(4) + compressed_len(4))
+pub fn write_header_v10(total_preproc_len: u32, config: &[u8], block_sizes: &[(u32, u32)]) -> Vec<u8> {
+    let mut hdr = Vec::with_capacity(header_size_v10(config.len(), block_sizes.len()));
+    hdr.extend_from_slice(MAGIC);
+    hdr.extend_from_slice(&FMT_V10.to_le_bytes());
+    hdr.extend_from_slice(&total_preproc_len.to_le_bytes());
+    hdr.extend_from_slice(&(config.len() as u16).to_le_bytes());
+    hdr.extend_from_slice(config);
+    hdr.extend_from_slice(&(block_sizes.len() as u16).to_le_bytes());
+    for &(preproc_len, compressed_len) in block_sizes {
+        hdr.extend_from_slice(&preproc_len.to_le_bytes());
+        hdr.extend_from_slice(&compressed_len.to_le_bytes());
+    }
+    hdr
+}
+
+/// Parsed V10 header.
+pub struct HeaderV10<'a> {
+    pub total_len: u32,
+    /// Serialized model configuration
+    pub config: &'a [u8],
+    /// (preproc_len, compressed_len) per block
+    pub blocks: Vec<(u32, u32)>,
+    /// Offset of the first block's compressed data
+    pub size: usize,
+}
+
+pub fn read_header_v10(data: &[u8]) -> Result<HeaderV10<'_>, String> {
+    if data.len() < 12 {
+        return Err("Data too short for V10 header".into());
+    }
+    let total_len = u32::from_le_bytes([data[6], data[7], data[8], data[9]]);
+    let cfg_len = u16::from_le_bytes([data[10], data[11]]) as usize;
+    if data.len() < 14 + cfg_len {
+        return Err("Data too short for V10 model config".into());
+    }
+    let config = &data[12..12 + cfg_len];
+    let nb_off = 12 + cfg_len;
+    let num_blocks = u16::from_le_bytes([data[nb_off], data[nb_off + 1]]) as usize;
+    let size = header_size_v10(cfg_len, num_blocks);
+    if data.len() < size {
+        return Err("Data too short for V10 block metadata".into());
+    }
+    let mut blocks = Vec::with_capacity(num_blocks);
+    for i in 0..num_blocks {
+        let off = nb_off + 2 + i * 8;
+        let preproc_len = u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]]);
+        let compressed_len = u32::from_le_bytes([data[off + 4], data[off + 5], data[off + 6], data[off + 7]]);
+        blocks.push((preproc_len, compressed_len));
+    }
+    Ok(HeaderV10 { total_len, config, blocks, size })
+}
+
+/// Header size for V10 format given config length and number of blocks.
+pub fn header_size_v10(config_len: usize, num_blocks: usize) -> usize {
+    14 + config_len + 8 * num_blocks
+}
+
 pub fn read_header(data: &[u8]) -> Result<(u16, u32), String> {
     if data.len() < HEADER_SIZE {
         return Err("Data too short for QICM header".into());
@@ -63,7 +124,7 @@ pub fn read_header(data: &[u8]) -> Result<(u16, u32), String> {
         return Err("Not a QICM file".into());
     }
     let ver = u16::from_le_bytes([data[4], data[5]]);
-    if ver != FMT_V7 && ver != FMT_V8 && ver != FMT_V9 {
+    if !(FMT_V7..=FMT_V10).contains(&ver) {
         return Err(format!("Unsupported version {ver}"));
     }
     let orig_len = u32::from_le_bytes([data[6], data[7], data[8], data[9]]);
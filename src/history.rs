@@ -0,0 +1,158 @@
+/// Longest suffix whose hash a history can keep
+pub const MAX_HASHED: usize = 64;
+
+const FNV_BASIS: u32 = 2166136261;
+const FNV_PRIME: u32 = 16777619;
+
+/// Byte history shared by every model of a coder.
+///
+/// Either keeps every byte or only the last `1 << window_bits` in a ring,
+/// which grows with the input up to that size; positions are absolute either
+/// way. On each push it hashes the suffixes the models asked for, so each
+/// context hash is computed once per byte however many models use it.
+///
+/// Suffixes are hashed with FNV-1a steps. Newest byte first, the hash of the
+/// last `k` bytes extends the hash of the last `k - 1`, so one pass up to the
+/// longest tracked length yields them all. Oldest byte first (`fnv(hist,
+/// n - k, n)` over a flat history, as V8/V9 need) every length is hashed
+/// from scratch.
+#[derive(Clone)]
+pub struct History {
+    buf: Vec<u8>,
+    /// Ring mask when windowed; an unbounded history grows `buf` instead
+    mask: Option<usize>,
+    n: usize,
+    /// Suffix lengths to hash, ascending
+    lengths: Vec<usize>,
+    /// hash[k]: hash of the last k bytes, for each tracked k <= n
+    hash: [u32; MAX_HASHED + 1],
+    /// The same hashes before the last push
+    prev_hash: [u32; MAX_HASHED + 1],
+    newest_first: bool,
+}
+
+impl History {
+    /// History keeping the last `1 << window_bits` bytes (everything if
+    /// `window_bits` is 0) and hashing suffixes of the given lengths, newest
+    /// byte first or oldest first.
+    pub fn new(window_bits: u8, lengths: &[usize], newest_first: bool) -> Self {
+        let mut lengths: Vec<usize> = lengths.iter().copied().filter(|&k| k > 0).collect();
+        lengths.sort_unstable();
+        lengths.dedup();
+        assert!(lengths.last().is_none_or(|&k| k <= MAX_HASHED), "suffix hash too long");
+        Self {
+            buf: Vec::new(),
+            mask: (window_bits != 0).then(|| (1 << window_bits) - 1),
+            n: 0,
+            lengths,
+            hash: [0; MAX_HASHED + 1],
+            prev_hash: [0; MAX_HASHED + 1],
+            newest_first,
+        }
+    }
+
+    /// Forget every byte, keeping the memory.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.n = 0;
+        self.hash = [0; MAX_HASHED + 1];
+        self.prev_hash = [0; MAX_HASHED + 1];
+    }
+
+    /// Bytes seen so far.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Oldest position still held.
+    #[inline(always)]
+    pub fn oldest(&self) -> usize {
+        match self.mask {
+            Some(mask) => self.n.saturating_sub(mask + 1),
+            None => 0,
+        }
+    }
+
+    /// Byte at absolute position `pos`, which must lie in `oldest()..len()`.
+    #[inline(always)]
+    pub fn at(&self, pos: usize) -> u8 {
+        debug_assert!(pos >= self.oldest() && pos < self.n);
+        match self.mask {
+            // Until the ring is full, `pos & mask == pos < buf.len()`
+            Some(mask) => unsafe { *self.buf.get_unchecked(pos & mask) },
+            None => self.buf[pos],
+        }
+    }
+
+    /// The `k`-th most recent byte (`back(1)` is the last one).
+    #[inline(always)]
+    pub fn back(&self, k: usize) -> u8 {
+        self.at(self.n - k)
+    }
+
+    /// Last byte, if any.
+    #[inline(always)]
+    pub fn last(&self) -> Option<u8> {
+        (self.n > 0).then(|| self.back(1))
+    }
+
+    /// Hash of the last `k` bytes; `k` must be tracked and at most `len()`.
+    #[inline(always)]
+    pub fn suffix_hash(&self, k: usize) -> u32 {
+        debug_assert!(k <= self.n && self.lengths.contains(&k));
+        self.hash[k]
+    }
+
+    /// `suffix_hash(k)` as it was before the last push.
+    #[inline(always)]
+    pub fn prev_suffix_hash(&self, k: usize) -> u32 {
+        debug_assert!(k < self.n && self.lengths.contains(&k));
+        self.prev_hash[k]
+    }
+
+    /// Hashes of the suffixes of the bytes before `end` with the given
+    /// ascending lengths, like `suffix_hash` would have given when `end` was
+    /// the length. Every suffix must lie in `oldest()..end`.
+    pub fn suffix_hashes_at(&self, end: usize, lengths: &[usize], out: &mut [u32]) {
+        if self.newest_first {
+            let mut h = FNV_BASIS;
+            let mut k = 0;
+            for (i, &len) in lengths.iter().enumerate() {
+                while k < len {
+                    k += 1;
+                    h = (h ^ self.at(end - k) as u32).wrapping_mul(FNV_PRIME);
+                }
+                out[i] = h;
+            }
+        } else {
+            for (i, &len) in lengths.iter().enumerate() {
+                let mut h = FNV_BASIS;
+                for pos in end - len..end {
+                    h = (h ^ self.at(pos) as u32).wrapping_mul(FNV_PRIME);
+                }
+                out[i] = h;
+            }
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        match self.mask {
+            Some(mask) if self.buf.len() > mask => self.buf[self.n & mask] = byte,
+            _ => self.buf.push(byte),
+        }
+        self.n += 1;
+        std::mem::swap(&mut self.hash, &mut self.prev_hash);
+        let tracked = self.lengths.partition_point(|&k| k <= self.n);
+        let mut hashes = [0u32; MAX_HASHED];
+        self.suffix_hashes_at(self.n, &self.lengths[..tracked], &mut hashes);
+        for (&k, &h) in self.lengths[..tracked].iter().zip(&hashes) {
+            self.hash[k] = h;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::history::History;
+
+/// Indirect context model.
+///
+/// Remembers the last two bytes that followed each order-1 and order-2
+/// context. That byte history, together with the previous byte, becomes a
+/// context of its own: after "ab … ac … ab" the history of `a` is "cb", so the
+/// bit models learn what tends to follow that alternation wherever it occurs.
+#[derive(Clone)]
+pub struct Indirect {
+    /// Bytes that followed each byte, most recent in the low 8 bits
+    t1: Vec<u16>,
+    /// Bytes that followed each byte pair
+    t2: Vec<u16>,
+}
+
+impl Default for Indirect {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Indirect {
+    pub fn new() -> Self {
+        Self {
+            t1: vec![0u16; 256],
+            t2: vec![0u16; 1 << 16],
+        }
+    }
+
+    /// Record `hist[n-1]` as following its order-1 and order-2 contexts.
+    pub fn update(&mut self, hist: &History) {
+        let n = hist.len();
+        if n < 2 {
+            return;
+        }
+        let c = hist.back(1) as u16;
+        let c1 = hist.back(2) as usize;
+        self.t1[c1] = (self.t1[c1] << 8) | c;
+        if n >= 3 {
+            let c2 = ((hist.back(3) as usize) << 8) | c1;
+            self.t2[c2] = (self.t2[c2] << 8) | c;
+        }
+    }
+
+    /// Byte-level base hashes for the next byte: (order-1, order-2) histories.
+    pub fn contexts(&self, hist: &History) -> [u32; 2] {
+        let n = hist.len();
+        let c1 = if n >= 1 { hist.back(1) as u32 } else { 0 };
+        let c2 = if n >= 2 { ((hist.back(2) as u32) << 8) | c1 } else { c1 };
+        let h1 = (c1 | (self.t1[c1 as usize] as u32) << 8).wrapping_mul(2654435761);
+        let h2 = (c1 | (self.t2[c2 as usize] as u32) << 8).wrapping_mul(2246822519);
+        [
+            h1.wrapping_mul(16777619) ^ 0x6789ABCD,
+            h2.wrapping_mul(16777619) ^ 0x789ABCDE,
+        ]
+    }
+}
@@ -1,4 +1,6 @@
+
 pub mod fnv;
+pub mod history;
 pub mod dict;
 pub mod pretrain;
 pub mod charfreq;
@@ -6,26 +8,51 @@ pub mod bitio;
 pub mod arithmetic;
 pub mod ppm;
 pub mod lzp;
+pub mod matcher;
 pub mod mixer;
+pub mod fixed;
+pub mod apm;
+pub mod bithist;
+pub mod bittable;
+pub mod indirect;
+pub mod word;
+pub mod column;
+pub mod markup;
+pub mod code;
+pub mod codec;
 pub mod format;
+pub mod config;
 
 use bitio::{BitWriter, BitReader};
-use arithmetic::{AEnc, ADec};
+use arithmetic::{ADec, AEnc, ByteDec, ByteEnc, Decoder, Encoder};
+use codec::{BlockCoder, PpmCoder};
+use config::{ModelConfig, CODER_BYTES};
 use mixer::ContextMixer;
-use ppm::PPM;
-use lzp::LZP;
 
 pub fn quantum_compress(text: &str) -> Vec<u8> {
     quantum_compress_threads(text, 0)
 }
 
 pub fn quantum_compress_threads(text: &str, threads: usize) -> Vec<u8> {
+    quantum_compress_with(text, &ModelConfig::default(), threads)
+}
+
+/// Compress at level 1 (fastest) to 9 (strongest); see `ModelConfig::for_level`.
+pub fn quantum_compress_level(text: &str, level: u8, threads: usize) -> Result<Vec<u8>, String> {
+    Ok(quantum_compress_with(text, &ModelConfig::for_level(level)?, threads))
+}
+
+/// Compress with an explicit model configuration. Always writes the V10 format.
+pub fn quantum_compress_with(text: &str, cfg: &ModelConfig, threads: usize) -> Vec<u8> {
     let raw = text.as_bytes();
     let data = dict::preprocess(text);
     let n = data.len();
     let orig_size = raw.len();
 
     let pretrain_data = dict::preprocess(pretrain::PRETRAIN);
+    // Nothing is gained from tables larger than the text can fill, and the
+    // decoder builds the same ones from the header
+    let cfg = &cfg.fitted_to(pretrain_data.len() + n);
 
     // Determine thread count
     let num_threads = if threads > 0 {
@@ -34,7 +61,7 @@ pub fn quantum_compress_threads(text: &str, threads: usize) -> Vec<u8> {
         std::thread::available_parallelism().map(|p| p.get()).unwrap_or(1)
     };
 
-    // Use single-threaded V8 for small files or 1 thread
+    // Use a single block for small files or 1 thread
     let min_block = 65536;
     let num_blocks = if num_threads <= 1 || n < min_block * 2 {
         1
@@ -42,10 +69,6 @@ pub fn quantum_compress_threads(text: &str, threads: usize) -> Vec<u8> {
         std::cmp::min(num_threads, n / min_block)
     };
 
-    if num_blocks == 1 {
-        return compress_single(&data, &pretrain_data, orig_size);
-    }
-
     // Split data into blocks
     let block_size = n / num_blocks;
     let mut blocks: Vec<&[u8]> = Vec::with_capacity(num_blocks);
@@ -55,38 +78,28 @@ pub fn quantum_compress_threads(text: &str, threads: usize) -> Vec<u8> {
         blocks.push(&data[start..end]);
     }
 
-    eprintln!("  Compressing with {} threads ({} blocks)...", num_blocks, num_blocks);
+    let compressed_blocks: Vec<Vec<u8>> = if num_blocks == 1 {
+        vec![compress_block(pretrained_coder(cfg, &pretrain_data), cfg.coder, blocks[0], true)]
+    } else {
+        eprintln!("  Compressing with {} threads ({} blocks)...", num_blocks, num_blocks);
 
-    // Pretrain one mixer, then clone for each block
-    let mut base_cm = ContextMixer::with_default_order();
-    base_cm.pretrain(&pretrain_data);
+        // Compress blocks in parallel, each thread building its own coder
+        let pretrain_data = &pretrain_data;
+        std::thread::scope(|s| {
+            let handles: Vec<_> = blocks.iter().map(|&block| {
+                s.spawn(move || compress_block(pretrained_coder(cfg, pretrain_data), cfg.coder, block, false))
+            }).collect();
 
-    // Compress blocks in parallel
-    let compressed_blocks: Vec<Vec<u8>> = std::thread::scope(|s| {
-        let handles: Vec<_> = blocks.iter().map(|block| {
-            let mut cm = base_cm.clone();
-            s.spawn(move || {
-                let mut bw = BitWriter::new();
-                {
-                    let mut enc = AEnc::new(&mut bw);
-                    for &byte in block.iter() {
-                        cm.encode_byte(byte, &mut enc);
-                    }
-                    enc.finish();
-                }
-                bw.data().to_vec()
-            })
-        }).collect();
-
-        handles.into_iter().map(|h| h.join().unwrap()).collect()
-    });
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    };
 
-    // Build V9 output
+    // Build V10 output
     let block_sizes: Vec<(u32, u32)> = blocks.iter().zip(compressed_blocks.iter())
         .map(|(blk, comp)| (blk.len() as u32, comp.len() as u32))
         .collect();
 
-    let mut result = format::write_header_v9(n as u32, &block_sizes);
+    let mut result = format::write_header_v10(n as u32, &cfg.to_bytes(), &block_sizes);
     for comp in &compressed_blocks {
         result.extend_from_slice(comp);
     }
@@ -101,35 +114,37 @@ pub fn quantum_compress_threads(text: &str, threads: usize) -> Vec<u8> {
     result
 }
 
-fn compress_single(data: &[u8], pretrain_data: &[u8], orig_size: usize) -> Vec<u8> {
-    let n = data.len();
-    let mut cm = ContextMixer::with_default_order();
+/// A coder for `cfg`, pretrained on the built-in text.
+fn pretrained_coder(cfg: &ModelConfig, pretrain_data: &[u8]) -> BlockCoder {
+    let mut cm = BlockCoder::with_config(cfg);
     cm.pretrain(pretrain_data);
+    cm
+}
 
+fn compress_block(mut cm: BlockCoder, coder: u8, block: &[u8], progress: bool) -> Vec<u8> {
+    let n = block.len();
     let mut bw = BitWriter::new();
+    let mut out = Vec::new();
     {
-        let mut enc = AEnc::new(&mut bw);
+        let mut enc = if coder == CODER_BYTES {
+            Encoder::Bytes(ByteEnc::new(&mut out))
+        } else {
+            Encoder::Bits(AEnc::new(&mut bw))
+        };
         let step = std::cmp::max(1, n / 20);
-        for i in 0..n {
-            if i % step == 0 {
+        for (i, &byte) in block.iter().enumerate() {
+            if progress && i % step == 0 {
                 eprint!("\r  Compressing: {}%", i * 100 / n);
             }
-            cm.encode_byte(data[i], &mut enc);
+            cm.encode_byte(byte, &mut enc);
         }
         enc.finish();
     }
-
-    let compressed = bw.data();
-    let mut result = format::write_header(format::FMT_V8, n as u32);
-    result.extend_from_slice(compressed);
-    eprintln!("\r  Compressing: 100%");
-    eprintln!(
-        "  {} \u{2192} {} bytes ({:.1}%)",
-        orig_size,
-        result.len(),
-        result.len() as f64 * 100.0 / orig_size as f64
-    );
-    result
+    if coder == CODER_BYTES {
+        out
+    } else {
+        bw.data().to_vec()
+    }
 }
 
 pub fn quantum_decompress(data: &[u8]) -> Result<String, String> {
@@ -153,7 +168,14 @@ pub fn quantum_decompress_threads(data: &[u8], threads: usize) -> Result<String,
             decompress_v8(&pretrain_data, orig_len, br)
         }
         format::FMT_V9 => {
-            decompress_v9(data, &pretrain_data, threads)?
+            let (_total_len, block_meta) = format::read_header_v9(data)?;
+            let hdr_size = format::header_size_v9(block_meta.len());
+            decompress_blocks(data, hdr_size, &block_meta, &ModelConfig::legacy(), &pretrain_data, threads)?
+        }
+        format::FMT_V10 => {
+            let hdr = format::read_header_v10(data)?;
+            let cfg = ModelConfig::from_bytes(hdr.config)?;
+            decompress_blocks(data, hdr.size, &hdr.blocks, &cfg, &pretrain_data, threads)?
         }
         _ => return Err(format!("Unsupported version {version}")),
     };
@@ -163,21 +185,17 @@ pub fn quantum_decompress_threads(data: &[u8], threads: usize) -> Result<String,
 }
 
 fn decompress_v7(pretrain_data: &[u8], orig_len: usize, br: BitReader) -> Vec<u8> {
-    let mut ppm = PPM::with_default_order();
-    ppm.pretrain(pretrain_data);
-    let mut lzp = LZP::new();
-    lzp.pretrain(pretrain_data);
+    let mut pc = PpmCoder::with_config(&ModelConfig::legacy());
+    pc.pretrain(pretrain_data);
 
-    let mut dec = ADec::new(br);
+    let mut dec = Decoder::Bits(ADec::new(br));
     let mut result = Vec::with_capacity(orig_len);
     let step = std::cmp::max(1, orig_len / 20);
     for i in 0..orig_len {
         if i % step == 0 {
             eprint!("\r  Decompressing: {}%", i * 100 / orig_len);
         }
-        let byte = ppm.decode_byte(&mut dec, lzp.pred, lzp.pred_len);
-        result.push(byte);
-        lzp.update(byte);
+        result.push(pc.decode_byte(&mut dec));
     }
     result
 }
@@ -186,7 +204,7 @@ fn decompress_v8(pretrain_data: &[u8], orig_len: usize, br: BitReader) -> Vec<u8
     let mut cm = ContextMixer::with_default_order();
     cm.pretrain(pretrain_data);
 
-    let mut dec = ADec::new(br);
+    let mut dec = Decoder::Bits(ADec::new(br));
     let mut result = Vec::with_capacity(orig_len);
     let step = std::cmp::max(1, orig_len / 20);
     for i in 0..orig_len {
@@ -199,41 +217,46 @@ fn decompress_v8(pretrain_data: &[u8], orig_len: usize, br: BitReader) -> Vec<u8
     result
 }
 
-fn decompress_v9(data: &[u8], pretrain_data: &[u8], _threads: usize) -> Result<Vec<u8>, String> {
-    let (_total_len, block_meta) = format::read_header_v9(data)?;
+fn decompress_blocks(
+    data: &[u8],
+    hdr_size: usize,
+    block_meta: &[(u32, u32)],
+    cfg: &ModelConfig,
+    pretrain_data: &[u8],
+    _threads: usize,
+) -> Result<Vec<u8>, String> {
     let num_blocks = block_meta.len();
-    let hdr_size = format::header_size_v9(num_blocks);
 
     // Calculate offsets for each block's compressed data
     let mut block_offsets = Vec::with_capacity(num_blocks);
     let mut offset = hdr_size;
-    for &(_preproc_len, compressed_len) in &block_meta {
+    for &(_preproc_len, compressed_len) in block_meta {
         block_offsets.push(offset);
         offset += compressed_len as usize;
     }
+    if offset > data.len() {
+        return Err("Truncated compressed data".into());
+    }
 
-    eprintln!("  Decompressing {} blocks in parallel...", num_blocks);
+    let block_data = |i: usize| {
+        let start = block_offsets[i];
+        &data[start..start + block_meta[i].1 as usize]
+    };
 
-    // Pretrain one mixer, clone for each block
-    let mut base_cm = ContextMixer::with_default_order();
-    base_cm.pretrain(pretrain_data);
+    if num_blocks == 1 {
+        let cm = pretrained_coder(cfg, pretrain_data);
+        return Ok(decompress_block(cm, cfg.coder, block_data(0), block_meta[0].0 as usize, true));
+    }
+
+    eprintln!("  Decompressing {} blocks in parallel...", num_blocks);
 
     let decoded_blocks: Vec<Vec<u8>> = std::thread::scope(|s| {
         let handles: Vec<_> = (0..num_blocks).map(|i| {
-            let mut cm = base_cm.clone();
-            let block_start = block_offsets[i];
-            let compressed_len = block_meta[i].1 as usize;
+            let block = block_data(i);
             let preproc_len = block_meta[i].0 as usize;
-            let block_data = &data[block_start..block_start + compressed_len];
             s.spawn(move || {
-                let br = BitReader::new(block_data);
-                let mut dec = ADec::new(br);
-                let mut result = Vec::with_capacity(preproc_len);
-                for _ in 0..preproc_len {
-                    let byte = cm.decode_byte(&mut dec);
-                    result.push(byte);
-                }
-                result
+                let cm = pretrained_coder(cfg, pretrain_data);
+                decompress_block(cm, cfg.coder, block, preproc_len, false)
             })
         }).collect();
 
@@ -248,3 +271,20 @@ fn decompress_v9(data: &[u8], pretrain_data: &[u8], _threads: usize) -> Result<V
     }
     Ok(result)
 }
+
+fn decompress_block(mut cm: BlockCoder, coder: u8, block: &[u8], preproc_len: usize, progress: bool) -> Vec<u8> {
+    let mut dec = if coder == CODER_BYTES {
+        Decoder::Bytes(ByteDec::new(block))
+    } else {
+        Decoder::Bits(ADec::new(BitReader::new(block)))
+    };
+    let mut result = Vec::with_capacity(preproc_len);
+    let step = std::cmp::max(1, preproc_len / 20);
+    for i in 0..preproc_len {
+        if progress && i % step == 0 {
+            eprint!("\r  Decompressing: {}%", i * 100 / preproc_len);
+        }
+        result.push(cm.decode_byte(&mut dec));
+    }
+    result
+}
@@ -1,12 +1,31 @@
 use rustc_hash::FxHashMap;
-use crate::fnv::fnv;
+use crate::config::ModelConfig;
+use crate::history::History;
 
-/// LZP longest-match predictor.
-/// table[hash] = pos where pos is the position of the byte that FOLLOWED the hashed context.
+/// Longest context the predictor looks up
+const MAX_CTX: usize = 24;
+const MIN_CTX: usize = 3;
+
+/// Where LZP keeps its context positions.
+#[derive(Clone)]
+enum Store {
+    /// One map entry per (context length, context), never evicted (V7–V9).
+    /// Needs an unbounded history.
+    Unbounded(FxHashMap<u32, usize>),
+    /// Fixed table of `chain`-entry buckets holding the most recent positions
+    /// per hash; candidates further back than `window` bytes are ignored
+    Bounded {
+        table: Vec<u32>,
+        chain: usize,
+        window: usize,
+    },
+}
+
+/// LZP longest-match predictor over the shared history.
+/// The table maps a hashed context to the position of the byte that FOLLOWED it.
 #[derive(Clone)]
 pub struct LZP {
-    pub hist: Vec<u8>,
-    table: FxHashMap<u32, usize>,
+    store: Store,
     pub pred: i32,
     pub pred_len: i32,
 }
@@ -14,31 +33,64 @@ pub struct LZP {
 impl LZP {
     pub fn new() -> Self {
         Self {
-            hist: Vec::new(),
-            table: FxHashMap::default(),
+            store: Store::Unbounded(FxHashMap::default()),
             pred: -1,
             pred_len: 0,
         }
     }
 
-    pub fn update(&mut self, byte: u8) {
-        let n = self.hist.len();
-        // Store: for context hist[n-ctx_len..n], the following byte will be at position n
-        let max_ctx = if n + 1 < 25 { n + 1 } else { 25 };
-        for ctx_len in 3..max_ctx {
-            let h = fnv(&self.hist, n - ctx_len, n);
-            self.table.insert(h, n);
+    /// Predictor with fixed memory: a `1 << table_bits` entry table, split
+    /// into buckets of `chain` candidates per hash, looking back at most
+    /// `1 << window_bits` bytes.
+    pub fn bounded(window_bits: u8, table_bits: u8, chain: u8) -> Self {
+        assert!(chain.is_power_of_two() && chain as usize <= 1 << table_bits);
+        Self {
+            store: Store::Bounded {
+                table: vec![0u32; 1 << table_bits],
+                chain: chain as usize,
+                window: 1 << window_bits,
+            },
+            pred: -1,
+            pred_len: 0,
+        }
+    }
+
+    /// The unbounded predictor, or a bounded one if the configuration sets
+    /// an LZP window.
+    pub fn with_config(cfg: &ModelConfig) -> Self {
+        if cfg.lzp_window == 0 {
+            Self::new()
+        } else {
+            Self::bounded(cfg.lzp_window, cfg.lzp_table, cfg.lzp_chain)
+        }
+    }
+
+    /// Suffix lengths LZP reads hashes of.
+    pub fn hash_lengths() -> std::ops::RangeInclusive<usize> {
+        MIN_CTX..=MAX_CTX
+    }
+
+    /// Record the byte just pushed to `hist` and find the match for the next one.
+    pub fn update(&mut self, hist: &History) {
+        let (pred, len) = match &mut self.store {
+            Store::Unbounded(table) => Self::update_unbounded(hist, table),
+            Store::Bounded { table, chain, window } => Self::update_bounded(hist, table, *chain, *window),
+        };
+        self.pred = pred;
+        self.pred_len = len;
+    }
+
+    fn update_unbounded(hist: &History, table: &mut FxHashMap<u32, usize>) -> (i32, i32) {
+        // Store: for context hist[n-ctx_len..n], the following byte is at position n
+        let n = hist.len() - 1;
+        for ctx_len in MIN_CTX..=n.min(MAX_CTX) {
+            table.insert(hist.prev_suffix_hash(ctx_len), n);
         }
-        self.hist.push(byte);
 
         // Find match for the NEXT byte
-        self.pred = -1;
-        self.pred_len = 0;
-        let n = self.hist.len();
-        let start_ctx = if n < 24 { n } else { 24 };
-        for ctx_len in (3..=start_ctx).rev() {
-            let h = fnv(&self.hist, n - ctx_len, n);
-            let pos = match self.table.get(&h) {
+        let n = hist.len();
+        for ctx_len in (MIN_CTX..=n.min(MAX_CTX)).rev() {
+            let pos = match table.get(&hist.suffix_hash(ctx_len)) {
                 Some(&p) => p,
                 None => continue,
             };
@@ -46,25 +98,53 @@ impl LZP {
                 continue;
             }
             // Verify: stored context hist[pos-ctx_len..pos] == current hist[n-ctx_len..n]
-            let mut ok = true;
-            for j in 0..ctx_len {
-                if self.hist[pos - ctx_len + j] != self.hist[n - ctx_len + j] {
-                    ok = false;
-                    break;
-                }
-            }
-            if !ok {
-                continue;
+            if (0..ctx_len).all(|j| hist.at(pos - ctx_len + j) == hist.at(n - ctx_len + j)) {
+                return (hist.at(pos) as i32, ctx_len as i32);
             }
-            self.pred = self.hist[pos] as i32;
-            self.pred_len = ctx_len as i32;
-            return;
         }
+        (-1, 0)
     }
 
-    pub fn pretrain(&mut self, data: &[u8]) {
-        for &b in data {
-            self.update(b);
+    /// Same lookup as the unbounded store. Positions are stored truncated to
+    /// 32 bits; anything outside the window or the history is stale.
+    fn update_bounded(hist: &History, table: &mut [u32], chain: usize, window: usize) -> (i32, i32) {
+        let bucket_mask = (table.len() / chain - 1) as u32;
+        let bucket_of = |h: u32, ctx_len: usize| {
+            let h = (h ^ (ctx_len as u32).wrapping_mul(0x9E3779B1)).wrapping_mul(2654435761);
+            ((h >> 7) & bucket_mask) as usize * chain
+        };
+
+        let n = hist.len() - 1;
+        for ctx_len in MIN_CTX..=n.min(MAX_CTX) {
+            let b = bucket_of(hist.prev_suffix_hash(ctx_len), ctx_len);
+            let bucket = &mut table[b..b + chain];
+            bucket.rotate_right(1);
+            bucket[0] = n as u32;
         }
+
+        // Find match for the NEXT byte
+        let n = hist.len();
+        let oldest = hist.oldest().max(n.saturating_sub(window));
+        for ctx_len in (MIN_CTX..=n.min(MAX_CTX)).rev() {
+            let b = bucket_of(hist.suffix_hash(ctx_len), ctx_len);
+            for &stored in &table[b..b + chain] {
+                // Distance back to the candidate, recovered from 32 bits
+                let back = (n as u32).wrapping_sub(stored) as usize;
+                if back == 0 || back > n || n - back < oldest + ctx_len {
+                    continue;
+                }
+                let pos = n - back;
+                if (0..ctx_len).all(|j| hist.at(pos - ctx_len + j) == hist.at(n - ctx_len + j)) {
+                    return (hist.at(pos) as i32, ctx_len as i32);
+                }
+            }
+        }
+        (-1, 0)
+    }
+}
+
+impl Default for LZP {
+    fn default() -> Self {
+        Self::new()
     }
 }
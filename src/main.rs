@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use claudcompress::config::{
+    ModelConfig, CODEC_MIXER, CODEC_PPM, DEFAULT_LEVEL, LR_DECAY, LR_ERROR, LR_FIXED, LR_RMS,
+    MAX_LEVEL, MIN_LEVEL, PPM_ADAPTIVE, PPM_ESCAPE, PPM_INTERPOLATED,
+};
 use std::fs;
 use std::path::PathBuf;
 
@@ -21,6 +25,34 @@ enum Commands {
         /// Number of threads (default: auto-detect)
         #[arg(short, long, default_value_t = 0)]
         threads: usize,
+        /// Compression level: 1 is fastest, 9 strongest; the options below
+        /// adjust it
+        #[arg(short, long, default_value_t = DEFAULT_LEVEL,
+              value_parser = clap::value_parser!(u8).range(MIN_LEVEL as i64..=MAX_LEVEL as i64))]
+        level: u8,
+        /// Context orders of the hashed order models, e.g. 0,1,2,3,4,6,8,16
+        #[arg(long, value_delimiter = ',')]
+        orders: Option<Vec<u8>>,
+        /// Context orders PPM interpolates over
+        #[arg(long, value_delimiter = ',')]
+        ppm_orders: Option<Vec<u8>>,
+        /// How PPM combines its orders
+        #[arg(long, value_enum)]
+        ppm: Option<PpmModel>,
+        /// log2 of the count total at which PPM contexts halve their counts
+        /// (0: never)
+        #[arg(long)]
+        ppm_halve: Option<u8>,
+        /// What codes the data: the context mixer, or PPM alone (much faster)
+        #[arg(long, value_enum)]
+        codec: Option<Codec>,
+        /// How the mixer's learning rates evolve
+        #[arg(long, value_enum)]
+        lr: Option<LrSchedule>,
+        /// Mix in f64 instead of integer arithmetic; the file may then only
+        /// decode on a machine that rounds the same way
+        #[arg(long)]
+        float_mixer: bool,
     },
     /// Decompress a .cqz file
     Decompress {
@@ -40,19 +72,104 @@ enum Commands {
         /// Number of threads (default: auto-detect)
         #[arg(short, long, default_value_t = 0)]
         threads: usize,
+        /// Compression level: 1 is fastest, 9 strongest
+        #[arg(short, long, default_value_t = DEFAULT_LEVEL,
+              value_parser = clap::value_parser!(u8).range(MIN_LEVEL as i64..=MAX_LEVEL as i64))]
+        level: u8,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum PpmModel {
+    /// Kneser-Ney interpolation of every order
+    Interpolated,
+    /// Escape coding with exclusion and secondary escape estimation
+    Escape,
+    /// Interpolation with modified Kneser-Ney discounts estimated online
+    Adaptive,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    Mixer,
+    Ppm,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LrSchedule {
+    /// Constant rates
+    Fixed,
+    /// Rates decaying as bits are coded
+    Decay,
+    /// Per-weight steps normalized by the RMS of their gradients
+    Rms,
+    /// Rates following the recent coding error
+    Error,
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Compress { file, output, threads } => {
+        Commands::Compress {
+            file,
+            output,
+            threads,
+            level,
+            orders,
+            ppm_orders,
+            ppm,
+            ppm_halve,
+            codec,
+            lr,
+            float_mixer,
+        } => {
+            let mut cfg = ModelConfig::for_level(level).unwrap_or_else(|e| {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            });
+            if let Some(orders) = orders {
+                cfg.orders = orders;
+            }
+            if let Some(orders) = ppm_orders {
+                cfg.ppm_orders = orders;
+            }
+            if let Some(ppm) = ppm {
+                cfg.ppm_model = match ppm {
+                    PpmModel::Interpolated => PPM_INTERPOLATED,
+                    PpmModel::Escape => PPM_ESCAPE,
+                    PpmModel::Adaptive => PPM_ADAPTIVE,
+                };
+            }
+            if let Some(halve) = ppm_halve {
+                cfg.ppm_halve = halve;
+            }
+            if let Some(codec) = codec {
+                cfg.codec = match codec {
+                    Codec::Mixer => CODEC_MIXER,
+                    Codec::Ppm => CODEC_PPM,
+                };
+            }
+            if let Some(lr) = lr {
+                cfg.lr_schedule = match lr {
+                    LrSchedule::Fixed => LR_FIXED,
+                    LrSchedule::Decay => LR_DECAY,
+                    LrSchedule::Rms => LR_RMS,
+                    LrSchedule::Error => LR_ERROR,
+                };
+            }
+            if float_mixer {
+                cfg.fixed_point = 0;
+            }
+            cfg.validate().unwrap_or_else(|e| {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            });
             let text = fs::read_to_string(&file).unwrap_or_else(|e| {
                 eprintln!("Error reading {}: {e}", file.display());
                 std::process::exit(1);
             });
-            let compressed = claudcompress::quantum_compress_threads(&text, threads);
+            let compressed = claudcompress::quantum_compress_with(&text, &cfg, threads);
             let out_path = output.unwrap_or_else(|| {
                 let mut p = file.clone();
                 let name = format!("{}.cqz", p.file_name().unwrap().to_string_lossy());
@@ -91,12 +208,12 @@ fn main() {
             });
             eprintln!("  Written to {}", out_path.display());
         }
-        Commands::Ratio { file, threads } => {
+        Commands::Ratio { file, threads, level } => {
             let text = fs::read_to_string(&file).unwrap_or_else(|e| {
                 eprintln!("Error reading {}: {e}", file.display());
                 std::process::exit(1);
             });
-            let _ = claudcompress::quantum_compress_threads(&text, threads);
+            let _ = claudcompress::quantum_compress_level(&text, level, threads);
         }
     }
 }
@@ -0,0 +1,269 @@
+/// Where the parser is relative to XML/HTML tags.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Text,
+    /// After `<`, reading the element name
+    TagName,
+    /// Inside a tag, between attributes or reading an attribute name
+    Attr,
+    /// Inside a quoted attribute value
+    Value(u8),
+    /// Inside `<!-- -->`
+    Comment,
+}
+
+/// Markdown block the current line belongs to.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Block {
+    #[default]
+    Para,
+    Heading,
+    List,
+    Quote,
+    Fence,
+}
+
+const STACK_DEPTH: usize = 16;
+
+/// HTML elements that never have content or a closing tag
+const VOID_ELEMENTS: [&[u8]; 14] = [
+    b"area", b"base", b"br", b"col", b"embed", b"hr", b"img", b"input", b"link", b"meta", b"param",
+    b"source", b"track", b"wbr",
+];
+
+/// Hash of an element name as `update_tags` builds it, case folded.
+const fn element_hash(name: &[u8]) -> u32 {
+    let mut h = 0u32;
+    let mut i = 0;
+    while i < name.len() {
+        h = (h ^ name[i].to_ascii_lowercase() as u32).wrapping_mul(16777619) | 1;
+        i += 1;
+    }
+    h
+}
+
+const VOID_HASHES: [u32; VOID_ELEMENTS.len()] = {
+    let mut hashes = [0; VOID_ELEMENTS.len()];
+    let mut i = 0;
+    while i < hashes.len() {
+        hashes[i] = element_hash(VOID_ELEMENTS[i]);
+        i += 1;
+    }
+    hashes
+};
+
+/// Number of distinct `selector` values
+pub const MARKUP_SELECTORS: usize = 4 * 5 * 2;
+
+/// Structure of XML, HTML and Markdown text.
+///
+/// Follows tags (element name, attribute name, quoted value, comment) and keeps
+/// a small stack of open elements, leaving out void elements and
+/// declarations, plus the Markdown block kind of the current line
+/// (heading, list item, quote, fenced code). Element and attribute names are
+/// predictable from that structure where plain byte orders are not.
+#[derive(Clone, Default)]
+pub struct MarkupContexts {
+    mode: Mode,
+    closing: bool,
+    /// `/` seen just before the end of the tag
+    self_closing: bool,
+    /// The tag opened with `!` or `?` (doctype, CDATA, processing
+    /// instruction), so it opens no element
+    declaration: bool,
+    /// Last three bytes, newest lowest, for spotting `<!--` and `-->`
+    recent: u32,
+    /// Hash of the name being read (element or attribute)
+    name: u32,
+    /// Hash of the element of the current tag
+    tag: u32,
+    /// Hash of the current attribute value
+    value: u32,
+    /// Hashes of the open elements, innermost last
+    stack: Vec<u32>,
+    block: Block,
+    in_fence: bool,
+    /// Bytes of the current line seen so far, saturating
+    line_pos: u32,
+    /// Backticks or tildes at the start of the current line
+    fence_run: u32,
+}
+
+impl MarkupContexts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn element(&self) -> u32 {
+        self.stack.last().copied().unwrap_or(0)
+    }
+
+    pub fn update(&mut self, byte: u8) {
+        self.update_tags(byte);
+        self.update_block(byte);
+    }
+
+    fn update_tags(&mut self, byte: u8) {
+        let recent = self.recent & 0xFF_FFFF;
+        self.recent = recent << 8 | byte as u32;
+        match self.mode {
+            Mode::Text => {
+                if byte == b'<' && !self.in_fence {
+                    self.mode = Mode::TagName;
+                    self.closing = false;
+                    self.self_closing = false;
+                    self.declaration = false;
+                    self.name = 0;
+                }
+            }
+            Mode::TagName => match byte {
+                b'/' if self.name == 0 => self.closing = true,
+                b'/' => self.self_closing = true,
+                b'!' | b'?' if self.name == 0 => self.declaration = true,
+                b'-' if recent == u32::from_be_bytes([0, b'<', b'!', b'-']) => {
+                    self.name = 0;
+                    self.mode = Mode::Comment;
+                }
+                b'>' => self.end_tag(false),
+                b' ' | b'\t' | b'\n' => {
+                    self.tag = self.name;
+                    self.name = 0;
+                    self.mode = Mode::Attr;
+                }
+                _ => self.name = (self.name ^ byte.to_ascii_lowercase() as u32).wrapping_mul(16777619) | 1,
+            },
+            Mode::Comment => {
+                if byte == b'>' && recent & 0xFFFF == u32::from_be_bytes([0, 0, b'-', b'-']) {
+                    self.mode = Mode::Text;
+                }
+            }
+            Mode::Attr => match byte {
+                b'>' => self.end_tag(true),
+                b'"' | b'\'' => {
+                    self.value = 0;
+                    self.mode = Mode::Value(byte);
+                }
+                b' ' | b'\t' | b'\n' => self.name = 0,
+                b'=' => {}
+                b'/' => self.self_closing = true,
+                _ => {
+                    self.self_closing = false;
+                    self.name = (self.name ^ byte as u32).wrapping_mul(16777619) | 1;
+                }
+            },
+            Mode::Value(quote) => {
+                if byte == quote {
+                    self.name = 0;
+                    self.mode = Mode::Attr;
+                } else {
+                    self.value = (self.value ^ byte as u32).wrapping_mul(16777619);
+                }
+            }
+        }
+    }
+
+    /// Close the current tag at `>`; `had_attrs` tells whether the element
+    /// name was already moved to `tag`.
+    fn end_tag(&mut self, had_attrs: bool) {
+        let tag = if had_attrs { self.tag } else { self.name };
+        if self.closing {
+            if let Some(i) = self.stack.iter().rposition(|&t| t == tag) {
+                self.stack.truncate(i);
+            }
+        } else if tag != 0 && !self.self_closing && !self.declaration && !VOID_HASHES.contains(&tag) {
+            if self.stack.len() == STACK_DEPTH {
+                self.stack.remove(0);
+            }
+            self.stack.push(tag);
+        }
+        self.tag = tag;
+        self.name = 0;
+        self.value = 0;
+        self.mode = Mode::Text;
+    }
+
+    fn update_block(&mut self, byte: u8) {
+        if byte == b'\n' {
+            if self.fence_run >= 3 {
+                self.in_fence = !self.in_fence;
+            }
+            self.line_pos = 0;
+            self.fence_run = 0;
+            self.block = if self.in_fence { Block::Fence } else { Block::Para };
+            return;
+        }
+        if self.line_pos == self.fence_run && (byte == b'`' || byte == b'~') {
+            self.fence_run += 1;
+        }
+        if self.line_pos == 0 && !self.in_fence {
+            self.block = match byte {
+                b'#' => Block::Heading,
+                b'-' | b'*' | b'+' => Block::List,
+                b'0'..=b'9' => Block::List,
+                b'>' => Block::Quote,
+                _ => Block::Para,
+            };
+        }
+        self.line_pos = self.line_pos.saturating_add(1);
+    }
+
+    /// Mixer weight-set selector: tag mode, block kind and line start.
+    pub fn selector(&self) -> usize {
+        let mode = match self.mode {
+            Mode::Text | Mode::Comment => 0,
+            Mode::TagName => 1,
+            Mode::Attr => 2,
+            Mode::Value(_) => 3,
+        };
+        (mode * 5 + self.block as usize) * 2 + (self.line_pos == 0) as usize
+    }
+
+    /// Byte-level base hashes for the next byte: (tag state + name or value
+    /// being read, enclosing element + block + previous byte).
+    pub fn contexts(&self, last: u8) -> [Option<u32>; 2] {
+        let tag = match self.mode {
+            Mode::Text | Mode::Comment => None,
+            Mode::TagName => Some(self.name ^ (self.closing as u32) << 31),
+            Mode::Attr => Some(self.tag.wrapping_mul(2246822519) ^ self.name),
+            Mode::Value(_) => Some((self.tag ^ self.name).wrapping_mul(3266489917) ^ self.value),
+        };
+        let tag = tag.map(|h| {
+            let h = (h ^ self.selector() as u32).wrapping_mul(2654435761);
+            h.wrapping_mul(16777619) ^ 0xEF012345
+        });
+        let text = (self.element().wrapping_mul(2246822519)
+            ^ (self.block as u32) << 8
+            ^ last as u32)
+            .wrapping_mul(2654435761);
+        [tag, Some(text.wrapping_mul(16777619) ^ 0xF0123456)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(text: &str) -> MarkupContexts {
+        let mut markup = MarkupContexts::new();
+        for &b in text.as_bytes() {
+            markup.update(b);
+        }
+        markup
+    }
+
+    #[test]
+    fn void_elements_and_declarations_open_nothing() {
+        let markup = after("<!DOCTYPE html>\n<?xml version=\"1.0\"?><p>a<br>b<IMG src=\"x.png\"><hr/>");
+        assert_eq!(markup.stack, [element_hash(b"p")]);
+        assert_eq!(after("<ul><li><img src=a><br></li>").element(), element_hash(b"ul"));
+    }
+
+    #[test]
+    fn comments_hide_their_contents() {
+        let markup = after("<div><!-- don't <b> close > here -->");
+        assert!(markup.mode == Mode::Text);
+        assert_eq!(markup.stack, [element_hash(b"div")]);
+        assert_eq!(after("<div><!-- a -- b --><i>").stack.len(), 2);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::history::History;
+
+/// Candidates kept per hash by a longest-match model
+const BUCKET: usize = 4;
+/// Furthest a new candidate is verified backwards; longer matches keep
+/// growing one byte at a time once found
+const MAX_VERIFY: usize = 1024;
+const LEN_BUCKETS: usize = 32;
+const HIT_LIMIT: u32 = 255;
+
+/// Flag on a match model spec: pick the longest of several candidates
+/// instead of the most recent one
+pub const MATCH_LONGEST: u8 = 0x80;
+
+/// Length bucket: exact below 16, then one bucket per doubling.
+#[inline(always)]
+fn len_bucket(len: usize) -> usize {
+    if len < 16 {
+        len
+    } else {
+        (12 + len.ilog2() as usize).min(LEN_BUCKETS - 1)
+    }
+}
+
+/// Hit statistic touched by the last prediction, needed for the update.
+#[derive(Clone, Copy)]
+pub struct MatchSlot {
+    ctx: usize,
+    expected: u8,
+}
+
+/// Match model: finds an earlier occurrence of the last `min_len` bytes and
+/// predicts the byte that followed it, bit by bit.
+///
+/// The match length is counted exactly and keeps growing while the match
+/// holds. Each bit's prediction is the learned probability that a match of
+/// that length is right, so it can enter the mixer directly.
+#[derive(Clone)]
+pub struct MatchModel {
+    min_len: usize,
+    longest: bool,
+    /// Positions following each hashed context (`BUCKET` per hash if longest)
+    table: Vec<u32>,
+    /// Entries in `table` minus one
+    mask: usize,
+    /// Position of the predicted byte and the current match length
+    ptr: usize,
+    len: usize,
+    /// (probability the expected bit is right, hit count) per length bucket
+    /// and expected bit
+    hits: Vec<(f64, u32)>,
+}
+
+impl MatchModel {
+    /// Model from a spec byte (minimum length, optionally `| MATCH_LONGEST`)
+    /// with a table of `2^table_bits` entries.
+    pub fn new(spec: u8, table_bits: u8) -> Self {
+        Self {
+            min_len: (spec & !MATCH_LONGEST) as usize,
+            longest: spec & MATCH_LONGEST != 0,
+            table: vec![0u32; 1 << table_bits],
+            mask: (1 << table_bits) - 1,
+            ptr: 0,
+            len: 0,
+            hits: vec![(0.75, 0); LEN_BUCKETS * 2],
+        }
+    }
+
+    /// Suffix length whose hash the model reads.
+    pub fn hash_length(&self) -> usize {
+        self.min_len
+    }
+
+    /// Follow or find a match after `hist` grew by one byte.
+    pub fn update(&mut self, hist: &History) {
+        let n = hist.len();
+        if self.len > 0 {
+            if hist.at(self.ptr) == hist.back(1) {
+                self.len += 1;
+                self.ptr += 1;
+            } else {
+                self.len = 0;
+            }
+        }
+        if n < self.min_len {
+            return;
+        }
+
+        let h = hist.suffix_hash(self.min_len) as usize;
+        let idx = if self.longest {
+            (h * BUCKET) & self.mask
+        } else {
+            h & self.mask
+        };
+        let width = if self.longest { BUCKET } else { 1 };
+
+        if self.len == 0 {
+            let oldest = hist.oldest();
+            for &cand in &self.table[idx..idx + width] {
+                let cand = cand as usize;
+                if cand == 0 || cand >= n || cand <= oldest {
+                    continue;
+                }
+                let limit = MAX_VERIFY.min(cand - oldest);
+                let mut len = 0;
+                while len < limit && hist.at(cand - 1 - len) == hist.at(n - 1 - len) {
+                    len += 1;
+                }
+                if len >= self.min_len && len > self.len {
+                    self.len = len;
+                    self.ptr = cand;
+                }
+            }
+        }
+
+        let bucket = &mut self.table[idx..idx + width];
+        bucket.rotate_right(1);
+        bucket[0] = n as u32;
+    }
+
+    /// Probability of a 1 bit for the bit at `node`, with the slot to update.
+    /// 0.5 and no slot without a match or once the byte has diverged from it.
+    #[inline(always)]
+    pub fn predict(&self, hist: &History, bit_pos: usize, node: u32) -> (f64, Option<MatchSlot>) {
+        if self.len == 0 {
+            return (0.5, None);
+        }
+        let pred = hist.at(self.ptr) as u32;
+        if (pred | 256) >> (8 - bit_pos) != node {
+            return (0.5, None);
+        }
+        let expected = ((pred >> (7 - bit_pos)) & 1) as u8;
+        let ctx = len_bucket(self.len) * 2 + expected as usize;
+        let p = self.hits[ctx].0;
+        (if expected == 1 { p } else { 1.0 - p }, Some(MatchSlot { ctx, expected }))
+    }
+
+    #[inline(always)]
+    pub fn update_bit(&mut self, slot: Option<MatchSlot>, bit: u8) {
+        if let Some(slot) = slot {
+            let (p, n) = &mut self.hits[slot.ctx];
+            let hit = (bit == slot.expected) as u32 as f64;
+            *p = (*p + (hit - *p) / (*n as f64 + 1.5)).clamp(1.0 / 4096.0, 4095.0 / 4096.0);
+            *n = (*n + 1).min(HIT_LIMIT);
+        }
+    }
+}
@@ -1,30 +1,70 @@
-use crate::arithmetic::{AEnc, ADec};
-use crate::fnv::fnv;
+use crate::apm::{Apm, ApmSlot, StageMixer};
+use crate::arithmetic::{Decoder, Encoder};
+use crate::bittable::BitTable;
+use crate::code::CodeContexts;
+use crate::column::ColumnContexts;
+use crate::config::{
+    ModelConfig, APM_FINAL, APM_MATCH, APM_ORDER1, APM_ORDER2, HASH_NEWEST_FIRST, LR_DECAY,
+    LR_ERROR, LR_RMS, MAX_MATCH_MODELS, MODEL_CODE, MODEL_COLUMNS, MODEL_INDIRECT, MODEL_MARKUP,
+    MODEL_WORDS, SEL_CLASS, SEL_MARKUP, SEL_MATCH, SEL_ORDER1, TABLE_BUCKETED, TABLE_COUNTS,
+    TABLE_STATES,
+};
+use crate::dict::CAP_MARKER;
+use crate::fixed::{self, FinalApm, FixedMix};
+use crate::history::History;
+use crate::indirect::Indirect;
 use crate::lzp::LZP;
-use crate::ppm::PPM;
+use crate::matcher::{MatchModel, MatchSlot};
+use crate::markup::{MarkupContexts, MARKUP_SELECTORS};
+use crate::ppm::{TreeDist, PPM};
+use crate::word::WordContexts;
 
 const MAX_ORD: usize = 6;
-// Models: 1 PPM + 7 orders + 3 skip/sparse + 1 word + 1 match = 13
-const N_ORDER_MODELS: usize = MAX_ORD + 1;
+// Models: 1 PPM + one per context order + 3 skip/sparse + 1 word + 1 match
+// (13 with the V8/V9 orders 0..=6)
 const N_EXTRA_MODELS: usize = 5; // skip1, skip2, sparse, word, match
-const N_BIT_MODELS: usize = N_ORDER_MODELS + N_EXTRA_MODELS;
-const N_MODELS: usize = 1 + N_BIT_MODELS; // 13 total
+// Optional context models append their bit models after these
+const MAX_BIT_MODELS: usize = 40;
+pub(crate) const MAX_MODELS: usize = 1 + MAX_BIT_MODELS + MAX_MATCH_MODELS;
 
-const BIT_SCALE: u64 = 1 << 15;
-const LR: f64 = 0.001;
+pub(crate) const LR: f64 = 0.001;
+
+// ── Gated mixing ──
+// Weight sets: the bit_pos set plus one per selector
+pub(crate) const MAX_SETS: usize = 5;
+const N_CLASSES: usize = 10;
+const N_MATCH_CTX: usize = 7;
+// Layer-1 sets see far fewer updates per row than the single V8 set
+pub(crate) const SET_LR: f64 = 0.02;
 
 // ── Residual NN correction ──
-const HIDDEN: usize = 6;
-const NN_LR: f64 = 0.01;
+pub(crate) const HIDDEN: usize = 6;
+pub(crate) const NN_LR: f64 = 0.01;
+
+// ── Learning-rate schedules ──
+// Decay: the rates scale from DECAY_START down to DECAY_END, halfway after
+// DECAY_HALF trained bits
+pub(crate) const DECAY_START: f64 = 1.5;
+pub(crate) const DECAY_END: f64 = 0.8;
+pub(crate) const DECAY_HALF: f64 = 200000.0;
+// RMS: step = rate * RMS_GAIN * g / (rms(g) + RMS_EPS)
+pub(crate) const RMS_DECAY: f64 = 0.9999;
+pub(crate) const RMS_GAIN: f64 = 0.07;
+pub(crate) const RMS_EPS: f64 = 1e-3;
+// Error-driven: rates scale with the recent squared error over its long-run
+// average, per bit position
+pub(crate) const ERR_FAST: f64 = 1.0 / 256.0;
+pub(crate) const ERR_SLOW: f64 = 1.0 / 4096.0;
+pub(crate) const ERR_MIN: f64 = 0.7;
+pub(crate) const ERR_MAX: f64 = 1.5;
 
 // ── Secondary Symbol Estimator (SSE) ──
-const SSE_BINS: usize = 64;
-const SSE_RATE: f64 = 0.005;
+pub(crate) const SSE_BINS: usize = 64;
+pub(crate) const SSE_RATE: f64 = 0.005;
 
-// ── Direct-mapped bit context table ──
-const BIT_TABLE_BITS: usize = 24;
-const BIT_TABLE_SIZE: usize = 1 << BIT_TABLE_BITS;
-const BIT_TABLE_MASK: u32 = (BIT_TABLE_SIZE - 1) as u32;
+// ── APM chain ──
+const MAX_APMS: usize = 3;
+const APM_ORDER2_BITS: usize = 18;
 
 // ── Lookup tables ──
 const STRETCH_TABLE_SIZE: usize = 4096;
@@ -35,12 +75,13 @@ static mut STRETCH_LUT: [f64; STRETCH_TABLE_SIZE + 1] = [0.0; STRETCH_TABLE_SIZE
 static mut SQUASH_LUT: [f64; SQUASH_TABLE_SIZE + 1] = [0.0; SQUASH_TABLE_SIZE + 1];
 static TABLES_INIT: std::sync::Once = std::sync::Once::new();
 
+#[allow(clippy::needless_range_loop)]
 fn init_tables() {
     TABLES_INIT.call_once(|| {
         unsafe {
             for i in 0..=STRETCH_TABLE_SIZE {
                 let mut p = i as f64 / STRETCH_TABLE_SIZE as f64;
-                p = p.max(1e-4).min(1.0 - 1e-4);
+                p = p.clamp(1e-4, 1.0 - 1e-4);
                 STRETCH_LUT[i] = (p / (1.0 - p)).ln();
             }
             for i in 0..=SQUASH_TABLE_SIZE {
@@ -52,14 +93,14 @@ fn init_tables() {
 }
 
 #[inline(always)]
-fn stretch_fast(p: f64) -> f64 {
+pub(crate) fn stretch_fast(p: f64) -> f64 {
     let idx = (p * STRETCH_TABLE_SIZE as f64) as usize;
     let idx = idx.min(STRETCH_TABLE_SIZE);
-    unsafe { *STRETCH_LUT.get_unchecked(idx) }
+    unsafe { *(*std::ptr::addr_of!(STRETCH_LUT)).get_unchecked(idx) }
 }
 
 #[inline(always)]
-fn squash_fast(x: f64) -> f64 {
+pub(crate) fn squash_fast(x: f64) -> f64 {
     if x >= SQUASH_RANGE {
         return 1.0 - 1e-5;
     }
@@ -68,81 +109,442 @@ fn squash_fast(x: f64) -> f64 {
     }
     let idx = ((x + SQUASH_RANGE) / (2.0 * SQUASH_RANGE) * SQUASH_TABLE_SIZE as f64) as usize;
     let idx = idx.min(SQUASH_TABLE_SIZE);
-    unsafe { *SQUASH_LUT.get_unchecked(idx) }
+    unsafe { *(*std::ptr::addr_of!(SQUASH_LUT)).get_unchecked(idx) }
+}
+
+/// Coarse class of a byte, used to select mixer weights.
+#[inline(always)]
+fn byte_class(b: u8) -> usize {
+    match b {
+        b' ' => 1,
+        b'\n' => 2,
+        b'a'..=b'z' => 3,
+        b'A'..=b'Z' => 4,
+        b'0'..=b'9' => 5,
+        b'.' | b',' | b';' | b':' | b'!' | b'?' => 6,
+        CAP_MARKER => 7,
+        129..=255 => 8,
+        _ => 9,
+    }
+}
+
+/// What picks the row of a mixer weight set.
+#[derive(Clone, Copy)]
+enum Selector {
+    BitPos,
+    Class,
+    Match,
+    Order1,
+    Markup,
 }
 
-/// Context mixer with 13 models, linear mixing + residual NN correction.
+impl Selector {
+    /// Number of selector contexts (each holds 8 bit-position rows).
+    fn contexts(self) -> usize {
+        match self {
+            Selector::BitPos => 1,
+            Selector::Class => N_CLASSES,
+            Selector::Match => N_MATCH_CTX,
+            Selector::Order1 => 256,
+            Selector::Markup => MARKUP_SELECTORS,
+        }
+    }
+}
+
+/// What an APM stage is keyed by.
+#[derive(Clone, Copy)]
+enum ApmCtx {
+    Order1,
+    Order2,
+    Match,
+}
+
+impl ApmCtx {
+    fn contexts(self) -> usize {
+        match self {
+            ApmCtx::Order1 => 1 << 16,
+            ApmCtx::Order2 => 1 << APM_ORDER2_BITS,
+            ApmCtx::Match => 256 + 32 * 2 * 8,
+        }
+    }
+}
+
+/// One bank of linear mixer weights, indexed by `context * 8 + bit_pos`.
+#[derive(Clone)]
+struct WeightSet {
+    sel: Selector,
+    w: Vec<[f64; MAX_MODELS]>,
+}
+
+/// PPM's prediction for the byte being coded, read one bit at a time.
+#[derive(Clone)]
+enum PpmBits {
+    /// Prefix sums of the full 256-entry distribution
+    Cum(Box<[f64; 257]>),
+    /// Sums over the nodes of the bit tree
+    Tree(Box<TreeDist>),
+}
+
+impl PpmBits {
+    #[inline(always)]
+    fn p1(&self, node: u32) -> f64 {
+        match self {
+            PpmBits::Cum(cum) => ContextMixer::ppm_bit_prob(cum, node),
+            PpmBits::Tree(tree) => tree.p1(node),
+        }
+    }
+}
+
+/// Running mean squared gradient of every trained weight, mirroring the
+/// mixer's weights, for RMS-normalized updates.
+#[derive(Clone)]
+struct Moments {
+    sets: Vec<Vec<[f64; MAX_MODELS]>>,
+    final_w: [[f64; MAX_SETS]; 8],
+    nn_w1: [[[f64; HIDDEN]; MAX_MODELS]; 8],
+    nn_b1: [[f64; HIDDEN]; 8],
+    nn_w2: [[f64; HIDDEN]; 8],
+    nn_b2: [f64; 8],
+}
+
+/// Gradient step on a row of weights, `w[i] += rate * err * x[i]` clamped to
+/// ±`limit`. With moments each step is instead divided by the running RMS of
+/// that weight's gradient.
+#[inline(always)]
+fn train(w: &mut [f64], x: &[f64], rate: f64, err: f64, limit: f64, moments: Option<&mut [f64]>) {
+    match moments {
+        None => {
+            for i in 0..w.len() {
+                w[i] = (w[i] + rate * err * x[i]).clamp(-limit, limit);
+            }
+        }
+        Some(v) => {
+            for i in 0..w.len() {
+                let g = err * x[i];
+                v[i] = RMS_DECAY * v[i] + (1.0 - RMS_DECAY) * g * g;
+                w[i] = (w[i] + rate * RMS_GAIN * g / (v[i].sqrt() + RMS_EPS)).clamp(-limit, limit);
+            }
+        }
+    }
+}
+
+/// `train` on the hidden units' weights for inputs `x`, unit `j`
+/// stepping by its own `err[j]`.
+#[inline(always)]
+fn train_units(
+    w: &mut [[f64; HIDDEN]],
+    x: &[f64],
+    rate: f64,
+    err: &[f64; HIDDEN],
+    limit: f64,
+    moments: Option<&mut [[f64; HIDDEN]]>,
+) {
+    match moments {
+        None => {
+            for i in 0..w.len() {
+                for j in 0..HIDDEN {
+                    w[i][j] = (w[i][j] + rate * err[j] * x[i]).clamp(-limit, limit);
+                }
+            }
+        }
+        Some(v) => {
+            for i in 0..w.len() {
+                for j in 0..HIDDEN {
+                    let g = err[j] * x[i];
+                    v[i][j] = RMS_DECAY * v[i][j] + (1.0 - RMS_DECAY) * g * g;
+                    w[i][j] = (w[i][j] + rate * RMS_GAIN * g / (v[i][j].sqrt() + RMS_EPS)).clamp(-limit, limit);
+                }
+            }
+        }
+    }
+}
+
+/// Hidden units' input sums: `bias[j] + sum(w[i][j] * x[i])`, added in
+/// input order.
+#[inline(always)]
+fn hidden_sums(w: &[[f64; HIDDEN]], x: &[f64], bias: &[f64; HIDDEN]) -> [f64; HIDDEN] {
+    let mut sum = *bias;
+    for i in 0..x.len() {
+        for j in 0..HIDDEN {
+            sum[j] += w[i][j] * x[i];
+        }
+    }
+    sum
+}
+
+/// `out[k] = sum(rows[k][i] * x[i])`, added in input order.
+#[inline(always)]
+fn dot_rows(rows: &[&[f64]], x: &[f64], out: &mut [f64]) {
+    for (k, w) in rows.iter().enumerate() {
+        let mut dot = 0.0f64;
+        for i in 0..x.len() {
+            dot += w[i] * x[i];
+        }
+        out[k] = dot;
+    }
+}
+
+/// Per-bit intermediate values shared between prediction and update.
+struct BitPrediction {
+    stretched: [f64; MAX_MODELS],
+    hidden: [f64; HIDDEN],
+    /// Row chosen in each weight set
+    rows: [usize; MAX_SETS],
+    /// Layer-1 outputs (logits), one per weight set
+    dots: [f64; MAX_SETS],
+    mixed: f64,
+    sse_bin: usize,
+    apm_slots: [ApmSlot; MAX_APMS],
+    match_slots: [Option<MatchSlot>; MAX_MATCH_MODELS],
+    /// Stretched stage outputs and their blend, for the stage mixer update
+    stage_x: [f64; 8],
+    final_p: f64,
+    p1: u64,
+}
+
+impl BitPrediction {
+    /// Prediction of the integer back end, which keeps its own state.
+    fn fixed(p1: u64, match_slots: [Option<MatchSlot>; MAX_MATCH_MODELS]) -> Self {
+        Self {
+            stretched: [0.0; MAX_MODELS],
+            hidden: [0.0; HIDDEN],
+            rows: [0; MAX_SETS],
+            dots: [0.0; MAX_SETS],
+            mixed: 0.0,
+            sse_bin: 0,
+            apm_slots: [ApmSlot::default(); MAX_APMS],
+            match_slots,
+            stage_x: [0.0; 8],
+            final_p: 0.0,
+            p1,
+        }
+    }
+}
+
+/// Context mixer over PPM plus hashed bit models, linear mixing + residual NN
+/// correction. The base set is one model per context order plus the
+/// skip/sparse/word/match models; optional context models add more inputs.
+///
+/// The linear mixer keeps one weight set per selector. With no selectors this
+/// is the single `bit_pos`-indexed set of V8/V9; otherwise each set's output
+/// is fed to a second layer that mixes them.
 #[derive(Clone)]
 pub struct ContextMixer {
-    mo: usize,
+    /// Context orders of the hashed order models
+    orders: Vec<usize>,
     pub(crate) ppm: PPM,
+    /// PPM's prediction for the current byte, rebuilt in place each byte
+    ppm_bits: PpmBits,
     pub(crate) lzp: LZP,
-    hist: Vec<u8>,
-    bit_table: Vec<[u16; 2]>,
+    /// History shared by every model, with the suffix hashes they read
+    hist: History,
+    bit_table: BitTable,
+    /// Number of hashed bit models and of mixer inputs (PPM + bit models +
+    /// match models)
+    n_bit: usize,
+    n_in: usize,
     /// Running word hash (resets on space/newline)
     word_hash: u32,
-    /// Linear mixer weights
-    linear_w: [[f64; N_MODELS]; 8],
-    nn_w1: [[[f64; N_MODELS]; HIDDEN]; 8],
+    indirect: Option<Indirect>,
+    words: Option<WordContexts>,
+    columns: Option<ColumnContexts>,
+    markup: Option<MarkupContexts>,
+    code: Option<CodeContexts>,
+    /// Match models, each a direct mixer input after the bit models
+    matches: Vec<MatchModel>,
+    /// Layer-1 linear mixer weights, one set per selector
+    sets: Vec<WeightSet>,
+    /// Layer-2 weights over the layer-1 outputs, per bit_pos
+    final_w: [[f64; MAX_SETS]; 8],
+    /// Hidden-unit weights per (bit_pos, input), the units side by side
+    nn_w1: [[[f64; HIDDEN]; MAX_MODELS]; 8],
     nn_b1: [[f64; HIDDEN]; 8],
     nn_w2: [[f64; HIDDEN]; 8],
     nn_b2: [f64; 8],
+    /// Whether the NN above adds its correction; without it the weights
+    /// stay untouched
+    residual: bool,
     /// SSE: adaptive probability refinement per (bit_pos, prob_bin)
     sse: [[f64; SSE_BINS]; 8],
+    /// Contexts of the APM stages chained after the SSE, and the stages
+    apm_ctxs: Vec<ApmCtx>,
+    apms: Vec<Apm>,
+    /// Learned blend of mixer, SSE and APM outputs; `None` keeps the fixed V8/V9 blend
+    stage_mix: Option<StageMixer>,
+    /// Learning-rate schedule (`LR_*`)
+    schedule: u8,
+    /// Bits trained so far, for the decaying rates
+    trained: u64,
+    /// Fast and slow averages of the squared error per bit_pos, for the
+    /// error-driven rates
+    err_avg: [(f64, f64); 8],
+    /// Gradient moments for the RMS-normalized rates
+    moments: Option<Box<Moments>>,
+    /// Integer back end replacing everything from stretch to the final
+    /// probability; the floating-point weights and stages above are then
+    /// left empty
+    fixed: Option<Box<FixedMix>>,
+    /// High-resolution stage between the final probability and the coder
+    final_apm: Option<Box<FinalApm>>,
+    /// Scale of the coder probabilities
+    bit_scale: u64,
 }
 
 impl ContextMixer {
     pub fn new(max_order: usize) -> Self {
-        init_tables();
+        Self::with_config(&ModelConfig {
+            max_order: max_order as u8,
+            ..ModelConfig::legacy()
+        })
+    }
 
-        let mut linear_w = [[0.0f64; N_MODELS]; 8];
-        for bp in 0..8 {
-            linear_w[bp][0] = 1.0;
+    pub fn with_config(cfg: &ModelConfig) -> Self {
+        init_tables();
+        let orders: Vec<usize> = cfg.effective_orders().into_iter().map(|o| o as usize).collect();
+
+        let indirect = (cfg.models & MODEL_INDIRECT != 0).then(Indirect::new);
+        let words = (cfg.models & MODEL_WORDS != 0).then(WordContexts::new);
+        let columns = (cfg.models & MODEL_COLUMNS != 0).then(ColumnContexts::new);
+        let markup = (cfg.models & MODEL_MARKUP != 0).then(MarkupContexts::new);
+        let code = (cfg.models & MODEL_CODE != 0).then(CodeContexts::new);
+        let n_bit = orders.len()
+            + N_EXTRA_MODELS
+            + if indirect.is_some() { 2 } else { 0 }
+            + if words.is_some() { 3 } else { 0 }
+            + if columns.is_some() { 3 } else { 0 }
+            + if markup.is_some() { 2 } else { 0 }
+            + if code.is_some() { 3 } else { 0 };
+        let matches: Vec<MatchModel> = cfg.matches.iter().map(|&spec| MatchModel::new(spec, cfg.match_table_bits)).collect();
+        let n_in = 1 + n_bit + matches.len();
+
+        let mut selectors = vec![Selector::BitPos];
+        for (bit, sel) in [
+            (SEL_CLASS, Selector::Class),
+            (SEL_MATCH, Selector::Match),
+            (SEL_ORDER1, Selector::Order1),
+            (SEL_MARKUP, Selector::Markup),
+        ] {
+            if cfg.selectors & bit != 0 {
+                selectors.push(sel);
+            }
         }
+        let fixed = cfg.fixed_point != 0;
+        let set_rows: Vec<usize> = selectors.iter().map(|sel| sel.contexts() * 8).collect();
+        let sets: Vec<WeightSet> = selectors
+            .into_iter()
+            .zip(&set_rows)
+            .map(|(sel, &rows)| {
+                let mut init = [0.0f64; MAX_MODELS];
+                init[0] = 1.0;
+                WeightSet { sel, w: if fixed { Vec::new() } else { vec![init; rows] } }
+            })
+            .collect();
+        let final_w = [[1.0 / sets.len() as f64; MAX_SETS]; 8];
+
+        let apm_ctxs: Vec<ApmCtx> = [
+            (APM_ORDER1, ApmCtx::Order1),
+            (APM_ORDER2, ApmCtx::Order2),
+            (APM_MATCH, ApmCtx::Match),
+        ]
+        .into_iter()
+        .filter(|&(bit, _)| cfg.apm & bit != 0)
+        .map(|(_, ctx)| ctx)
+        .collect();
+        let apms: Vec<Apm> = if fixed {
+            Vec::new()
+        } else {
+            apm_ctxs.iter().map(|ctx| Apm::new(ctx.contexts())).collect()
+        };
+        let stage_mix = if apms.is_empty() {
+            None
+        } else {
+            Some(StageMixer::new(2 + apms.len()))
+        };
 
         let phi = 0.618033988749895f64;
-        let scale = 0.1 / (N_MODELS as f64).sqrt();
-        let mut nn_w1 = [[[0.0f64; N_MODELS]; HIDDEN]; 8];
+        let scale = 0.1 / (n_in as f64).sqrt();
+        let mut nn_w1 = [[[0.0f64; HIDDEN]; MAX_MODELS]; 8];
         let mut nn_b1 = [[0.0f64; HIDDEN]; 8];
-        for bp in 0..8 {
-            for j in 0..HIDDEN {
-                for i in 0..N_MODELS {
-                    let seed = (bp * HIDDEN * N_MODELS + j * N_MODELS + i) as f64;
-                    nn_w1[bp][j][i] = ((seed * phi).fract() - 0.5) * 2.0 * scale;
+        for (bp, (w1, b1)) in nn_w1.iter_mut().zip(&mut nn_b1).enumerate() {
+            for (j, b) in b1.iter_mut().enumerate() {
+                for (i, unit) in w1[..n_in].iter_mut().enumerate() {
+                    let seed = (bp * HIDDEN * n_in + j * n_in + i) as f64;
+                    unit[j] = ((seed * phi).fract() - 0.5) * 2.0 * scale;
                 }
-                nn_b1[bp][j] = ((j as f64 * phi * 7.0).fract() - 0.5) * 0.05;
+                *b = ((j as f64 * phi * 7.0).fract() - 0.5) * 0.05;
             }
         }
 
+        let ppm = PPM::with_config(cfg);
+        let mut hashed: Vec<usize> = orders.iter().chain(ppm.orders()).copied().collect();
+        hashed.extend(LZP::hash_lengths());
+        hashed.extend(matches.iter().map(|mm| mm.hash_length()));
+        let hist = History::new(cfg.history_window, &hashed, cfg.context_hash == HASH_NEWEST_FIRST);
+        let apm_sizes: Vec<usize> = apm_ctxs.iter().map(|ctx| ctx.contexts()).collect();
+        let fixed = fixed.then(|| {
+            Box::new(FixedMix::new(n_in, &set_rows, &apm_sizes, cfg.lr_schedule, cfg.residual_nn != 0, &nn_w1, &nn_b1))
+        });
+
         Self {
-            mo: max_order,
-            ppm: PPM::new(max_order),
-            lzp: LZP::new(),
-            hist: Vec::new(),
-            bit_table: vec![[0u16; 2]; BIT_TABLE_SIZE],
+            orders,
+            ppm,
+            ppm_bits: if cfg.ppm_tree != 0 {
+                PpmBits::Tree(Box::new(TreeDist::new()))
+            } else {
+                PpmBits::Cum(Box::new([0.0; 257]))
+            },
+            lzp: LZP::with_config(cfg),
+            hist,
+            bit_table: match cfg.bit_table {
+                TABLE_COUNTS => BitTable::counts(cfg.bit_table_bits),
+                TABLE_STATES => BitTable::states(cfg.bit_table_bits, n_bit),
+                TABLE_BUCKETED => BitTable::buckets(cfg.bit_table_bits, n_bit, false),
+                _ => BitTable::buckets(cfg.bit_table_bits, n_bit, true),
+            },
+            n_bit,
+            n_in,
             word_hash: 0,
-            linear_w,
+            indirect,
+            words,
+            columns,
+            markup,
+            code,
+            matches,
+            moments: (cfg.lr_schedule == LR_RMS && fixed.is_none()).then(|| {
+                Box::new(Moments {
+                    sets: sets.iter().map(|set| vec![[0.0; MAX_MODELS]; set.w.len()]).collect(),
+                    final_w: [[0.0; MAX_SETS]; 8],
+                    nn_w1: [[[0.0; HIDDEN]; MAX_MODELS]; 8],
+                    nn_b1: [[0.0; HIDDEN]; 8],
+                    nn_w2: [[0.0; HIDDEN]; 8],
+                    nn_b2: [0.0; 8],
+                })
+            }),
+            sets,
+            final_w,
             nn_w1,
             nn_b1,
-            nn_w2: {
-                let mut w = [[0.0f64; HIDDEN]; 8];
-                for bp in 0..8 {
-                    for j in 0..HIDDEN {
-                        w[bp][j] = 0.15;
-                    }
-                }
-                w
-            },
+            nn_w2: [[0.15f64; HIDDEN]; 8],
             nn_b2: [0.0f64; 8],
+            residual: cfg.residual_nn != 0,
             sse: {
                 let mut s = [[0.0f64; SSE_BINS]; 8];
-                for bp in 0..8 {
-                    for bin in 0..SSE_BINS {
-                        s[bp][bin] = (bin as f64 + 0.5) / SSE_BINS as f64;
+                for row in s.iter_mut() {
+                    for (bin, v) in row.iter_mut().enumerate() {
+                        *v = (bin as f64 + 0.5) / SSE_BINS as f64;
                     }
                 }
                 s
             },
+            apm_ctxs,
+            apms,
+            stage_mix,
+            schedule: cfg.lr_schedule,
+            trained: 0,
+            err_avg: [(0.25, 0.25); 8],
+            fixed,
+            final_apm: (cfg.apm & APM_FINAL != 0).then(|| Box::new(FinalApm::new(cfg.prob_bits))),
+            bit_scale: 1 << cfg.prob_bits,
         }
     }
 
@@ -151,36 +553,64 @@ impl ContextMixer {
     }
 
     pub fn pretrain(&mut self, data: &[u8]) {
-        self.ppm.pretrain(data);
-        self.lzp.pretrain(data);
+        // PPM and LZP see the text first; the bit models then train against
+        // LZP's final state, as they always have, over the same text pushed
+        // into the history again
+        for &byte in data {
+            self.ppm.update(&self.hist, byte);
+            self.hist.push(byte);
+            self.lzp.update(&self.hist);
+        }
+        self.ppm.finish_pretrain(data);
+        self.hist.clear();
 
         for &byte in data {
             let mut node: u32 = 1;
-            let n = self.hist.len();
-            let max_order = std::cmp::min(self.mo + 1, n + 1);
-
             // Precompute byte-level hashes once per byte
-            let (byte_bases, active) = self.precompute_byte_hashes(n, max_order);
-
-            for bit_pos in 0..8u32 {
-                let bit = ((byte >> (7 - bit_pos)) & 1) as usize;
-                let node_part = node.wrapping_mul(2654435761);
-
-                for m in 0..N_BIT_MODELS {
-                    let h = if active[m] { byte_bases[m] ^ node_part } else { 0 };
-                    let idx = (h & BIT_TABLE_MASK) as usize;
-                    let entry = unsafe { self.bit_table.get_unchecked_mut(idx) };
-                    entry[bit] = entry[bit].saturating_add(1);
+            let (byte_bases, active) = self.precompute_byte_hashes();
+
+            for bit_pos in 0..8 {
+                let bit = (byte >> (7 - bit_pos)) & 1;
+                let mut slots = [0usize; MAX_BIT_MODELS];
+                let slots = &mut slots[..self.n_bit];
+                self.bit_table.slots(&byte_bases, &active, node, bit_pos, slots);
+                for (m, &slot) in slots.iter().enumerate() {
+                    self.bit_table.train(m, slot, bit);
+                }
+                for mm in self.matches.iter_mut() {
+                    let (_, slot) = mm.predict(&self.hist, bit_pos, node);
+                    mm.update_bit(slot, bit);
                 }
                 node = node * 2 + bit as u32;
             }
-            self.update_word_hash(byte);
-            self.hist.push(byte);
+            self.advance(byte);
         }
 
-        for entry in self.bit_table.iter_mut() {
-            entry[0] /= 2;
-            entry[1] /= 2;
+        self.bit_table.finish_pretrain();
+    }
+
+    /// Per-byte state updates shared by pretraining and coding.
+    #[inline(always)]
+    fn advance(&mut self, byte: u8) {
+        self.update_word_hash(byte);
+        self.hist.push(byte);
+        if let Some(ind) = &mut self.indirect {
+            ind.update(&self.hist);
+        }
+        if let Some(words) = &mut self.words {
+            words.update(byte);
+        }
+        if let Some(columns) = &mut self.columns {
+            columns.update(byte);
+        }
+        if let Some(markup) = &mut self.markup {
+            markup.update(byte);
+        }
+        if let Some(code) = &mut self.code {
+            code.update(byte);
+        }
+        for mm in self.matches.iter_mut() {
+            mm.update(&self.hist);
         }
     }
 
@@ -197,51 +627,51 @@ impl ContextMixer {
     /// Precompute byte-level base hashes (constant across all 8 bit positions).
     /// Returns (base_hashes, active_mask). Final bit hash = base ^ node_part.
     #[inline(always)]
-    fn precompute_byte_hashes(
-        &self,
-        n: usize,
-        max_order: usize,
-    ) -> ([u32; N_BIT_MODELS], [bool; N_BIT_MODELS]) {
-        let mut base = [0u32; N_BIT_MODELS];
-        let mut active = [false; N_BIT_MODELS];
+    fn precompute_byte_hashes(&self) -> ([u32; MAX_BIT_MODELS], [bool; MAX_BIT_MODELS]) {
+        let n = self.hist.len();
+        let mut base = [0u32; MAX_BIT_MODELS];
+        let mut active = [false; MAX_BIT_MODELS];
 
         // Order models: base = fnv_hash * FNV_PRIME
-        for order in 0..max_order {
+        for (i, &order) in self.orders.iter().enumerate() {
+            if order > n {
+                break;
+            }
             let byte_h = if order == 0 {
                 0u32
             } else {
-                fnv(&self.hist, n - order, n)
+                self.hist.suffix_hash(order)
             };
-            base[order] = byte_h.wrapping_mul(16777619);
-            active[order] = true;
+            base[i] = byte_h.wrapping_mul(16777619);
+            active[i] = true;
         }
         // Inactive orders: active stays false, hash stays 0
 
-        let oe = N_ORDER_MODELS; // order_end
+        let oe = self.orders.len(); // order_end
 
         // Skip-1: hash(byte[-1], byte[-3])
         if n >= 3 {
-            let h = (self.hist[n - 1] as u32)
+            let h = (self.hist.back(1) as u32)
                 .wrapping_mul(16777619)
-                ^ (self.hist[n - 3] as u32).wrapping_mul(2654435761);
+                ^ (self.hist.back(3) as u32).wrapping_mul(2654435761);
             base[oe] = h.wrapping_mul(16777619) ^ 0x12345678;
             active[oe] = true;
         }
 
         // Skip-2: hash(byte[-1], byte[-4])
         if n >= 4 {
-            let h = (self.hist[n - 1] as u32)
+            let h = (self.hist.back(1) as u32)
                 .wrapping_mul(16777619)
-                ^ (self.hist[n - 4] as u32).wrapping_mul(2654435761);
+                ^ (self.hist.back(4) as u32).wrapping_mul(2654435761);
             base[oe + 1] = h.wrapping_mul(16777619) ^ 0x23456789;
             active[oe + 1] = true;
         }
 
         // Sparse: hash(byte[-2], byte[-4])
         if n >= 4 {
-            let h = (self.hist[n - 2] as u32)
+            let h = (self.hist.back(2) as u32)
                 .wrapping_mul(16777619)
-                ^ (self.hist[n - 4] as u32).wrapping_mul(2654435761);
+                ^ (self.hist.back(4) as u32).wrapping_mul(2654435761);
             base[oe + 2] = h.wrapping_mul(16777619) ^ 0x3456789A;
             active[oe + 2] = true;
         }
@@ -268,24 +698,40 @@ impl ContextMixer {
             active[oe + 4] = true;
         }
 
-        (base, active)
-    }
-
-    /// Combine precomputed byte bases with bit-tree node to get final hashes.
-    #[inline(always)]
-    fn make_bit_hashes(
-        byte_bases: &[u32; N_BIT_MODELS],
-        active: &[bool; N_BIT_MODELS],
-        node: u32,
-    ) -> [u32; N_BIT_MODELS] {
-        let node_part = node.wrapping_mul(2654435761);
-        let mut hashes = [0u32; N_BIT_MODELS];
-        for i in 0..N_BIT_MODELS {
-            if active[i] {
-                hashes[i] = byte_bases[i] ^ node_part;
+        // Optional context models, in config order
+        let mut k = oe + N_EXTRA_MODELS;
+        if let Some(ind) = &self.indirect {
+            for h in ind.contexts(&self.hist) {
+                base[k] = h;
+                active[k] = true;
+                k += 1;
+            }
+        }
+        let last = self.hist.last().unwrap_or(0);
+        let mut push = |hashes: &[Option<u32>]| {
+            for &h in hashes {
+                if let Some(h) = h {
+                    base[k] = h;
+                    active[k] = true;
+                }
+                k += 1;
             }
+        };
+        if let Some(words) = &self.words {
+            push(&words.contexts(last));
+        }
+        if let Some(columns) = &self.columns {
+            push(&columns.contexts(last));
+        }
+        if let Some(markup) = &self.markup {
+            push(&markup.contexts(last));
+        }
+        if let Some(code) = &self.code {
+            push(&code.contexts());
         }
-        hashes
+        debug_assert_eq!(k, self.n_bit);
+
+        (base, active)
     }
 
     #[inline(always)]
@@ -304,246 +750,404 @@ impl ContextMixer {
         }
     }
 
+    /// Row of each weight set for the current bit.
     #[inline(always)]
-    fn bit_predict_direct(&self, h: u32) -> f64 {
-        let entry = unsafe { self.bit_table.get_unchecked((h & BIT_TABLE_MASK) as usize) };
-        let total = (entry[0] as u32 + entry[1] as u32) as f64;
-        if total == 0.0 {
-            0.5
-        } else {
-            (entry[1] as f64 + 0.5) / (total + 1.0)
+    fn select_rows(&self, bit_pos: usize, node: u32) -> [usize; MAX_SETS] {
+        let prev = self.hist.last();
+        let mut rows = [0usize; MAX_SETS];
+        for (k, set) in self.sets.iter().enumerate() {
+            let ctx = match set.sel {
+                Selector::BitPos => 0,
+                Selector::Class => prev.map_or(0, byte_class),
+                Selector::Match => self.match_ctx(bit_pos, node),
+                Selector::Order1 => prev.map_or(0, |b| b as usize),
+                Selector::Markup => self.markup.as_ref().map_or(0, |m| m.selector()),
+            };
+            rows[k] = ctx * 8 + bit_pos;
         }
+        rows
     }
 
+    /// LZP match-length bucket split by the bit the match expects next.
+    /// 0 when there is no match or the current byte has already diverged from it.
     #[inline(always)]
-    fn bit_update_direct(&mut self, h: u32, bit: usize) {
-        let entry = unsafe { self.bit_table.get_unchecked_mut((h & BIT_TABLE_MASK) as usize) };
-        entry[bit] = entry[bit].saturating_add(1);
-        if entry[0] as u32 + entry[1] as u32 > 16 {
-            entry[0] = (entry[0] + 1) >> 1;
-            entry[1] = (entry[1] + 1) >> 1;
+    fn match_ctx(&self, bit_pos: usize, node: u32) -> usize {
+        if self.lzp.pred < 0 || self.lzp.pred_len < 4 {
+            return 0;
         }
+        let pred = self.lzp.pred as u32;
+        if (pred | 256) >> (8 - bit_pos) != node {
+            return 0;
+        }
+        let bucket = if self.lzp.pred_len >= 16 {
+            2
+        } else if self.lzp.pred_len >= 8 {
+            1
+        } else {
+            0
+        };
+        1 + bucket * 2 + ((pred >> (7 - bit_pos)) & 1) as usize
     }
 
     #[inline(always)]
     fn forward(
         &self,
         bit_pos: usize,
-        inputs: &[f64; N_MODELS],
-    ) -> (f64, [f64; N_MODELS], [f64; HIDDEN]) {
-        let mut stretched = [0.0f64; N_MODELS];
-        for i in 0..N_MODELS {
+        inputs: &[f64; MAX_MODELS],
+        rows: &[usize; MAX_SETS],
+    ) -> (f64, [f64; MAX_MODELS], [f64; HIDDEN], [f64; MAX_SETS]) {
+        let mut stretched = [0.0f64; MAX_MODELS];
+        for i in 0..self.n_in {
             stretched[i] = stretch_fast(inputs[i]);
         }
 
-        let w = &self.linear_w[bit_pos];
-        let mut linear_logit = 0.0f64;
-        for i in 0..N_MODELS {
-            linear_logit += w[i] * stretched[i];
+        let x = &stretched[..self.n_in];
+        let mut set_rows: [&[f64]; MAX_SETS] = [&[]; MAX_SETS];
+        for (k, set) in self.sets.iter().enumerate() {
+            set_rows[k] = &set.w[rows[k]][..self.n_in];
         }
+        let mut dots = [0.0f64; MAX_SETS];
+        dot_rows(&set_rows[..self.sets.len()], x, &mut dots);
+        let linear_logit = if self.sets.len() == 1 {
+            dots[0]
+        } else {
+            let v = &self.final_w[bit_pos];
+            let mut x = 0.0f64;
+            for k in 0..self.sets.len() {
+                x += v[k] * dots[k].clamp(-SQUASH_RANGE, SQUASH_RANGE);
+            }
+            x
+        };
 
         let mut hidden = [0.0f64; HIDDEN];
-        for j in 0..HIDDEN {
-            let mut sum = self.nn_b1[bit_pos][j];
-            for i in 0..N_MODELS {
-                sum += self.nn_w1[bit_pos][j][i] * stretched[i];
+        let mut correction = 0.0;
+        if self.residual {
+            let sums = hidden_sums(&self.nn_w1[bit_pos][..self.n_in], x, &self.nn_b1[bit_pos]);
+            hidden = sums.map(squash_fast);
+            correction = self.nn_b2[bit_pos];
+            for (&w, &h) in self.nn_w2[bit_pos].iter().zip(&hidden) {
+                correction += w * h;
             }
-            hidden[j] = squash_fast(sum);
-        }
-        let mut correction = self.nn_b2[bit_pos];
-        for j in 0..HIDDEN {
-            correction += self.nn_w2[bit_pos][j] * hidden[j];
         }
 
         let mixed = squash_fast(linear_logit + correction);
-        (mixed, stretched, hidden)
+        (mixed, stretched, hidden, dots)
     }
 
+    /// Scale of the learning rates for this bit under the decaying or
+    /// error-driven schedules; 1 otherwise.
     #[inline(always)]
-    fn backward(
-        &mut self,
-        bit_pos: usize,
-        stretched: &[f64; N_MODELS],
-        hidden: &[f64; HIDDEN],
-        mixed: f64,
-        target: f64,
-    ) {
-        let err = target - mixed;
-
-        let w = &mut self.linear_w[bit_pos];
-        for i in 0..N_MODELS {
-            w[i] = (w[i] + LR * err * stretched[i]).max(-8.0).min(8.0);
+    fn rate_scale(&self, bit_pos: usize) -> f64 {
+        match self.schedule {
+            LR_DECAY => {
+                DECAY_END + (DECAY_START - DECAY_END) * DECAY_HALF / (DECAY_HALF + self.trained as f64)
+            }
+            LR_ERROR => {
+                let (fast, slow) = self.err_avg[bit_pos];
+                (fast / slow).clamp(ERR_MIN, ERR_MAX)
+            }
+            _ => 1.0,
         }
+    }
 
-        for j in 0..HIDDEN {
-            self.nn_w2[bit_pos][j] =
-                (self.nn_w2[bit_pos][j] + NN_LR * err * hidden[j]).max(-4.0).min(4.0);
+    #[inline(always)]
+    fn backward(&mut self, bit_pos: usize, pr: &BitPrediction, target: f64) {
+        let stretched = &pr.stretched[..self.n_in];
+        let hidden = &pr.hidden;
+        let err = target - pr.mixed;
+        let scale = self.rate_scale(bit_pos);
+        let (lr, set_lr, nn_lr) = (LR * scale, SET_LR * scale, NN_LR * scale);
+        let mut moments = self.moments.as_deref_mut();
+
+        if self.sets.len() == 1 {
+            let row = pr.rows[0];
+            let v = moments.as_mut().map(|m| &mut m.sets[0][row][..self.n_in]);
+            train(&mut self.sets[0].w[row][..self.n_in], stretched, lr, err, 8.0, v);
+        } else {
+            // Each layer-1 set learns from its own output, layer 2 from the final one
+            for (k, set) in self.sets.iter_mut().enumerate() {
+                let set_err = target - squash_fast(pr.dots[k]);
+                let row = pr.rows[k];
+                let v = moments.as_mut().map(|m| &mut m.sets[k][row][..self.n_in]);
+                train(&mut set.w[row][..self.n_in], stretched, set_lr, set_err, 8.0, v);
+            }
+            let n_sets = self.sets.len();
+            let x = pr.dots.map(|dot| dot.clamp(-SQUASH_RANGE, SQUASH_RANGE));
+            let v = moments.as_mut().map(|m| &mut m.final_w[bit_pos][..n_sets]);
+            train(&mut self.final_w[bit_pos][..n_sets], &x[..n_sets], lr, err, 8.0, v);
         }
-        self.nn_b2[bit_pos] = (self.nn_b2[bit_pos] + NN_LR * err).max(-4.0).min(4.0);
 
-        for j in 0..HIDDEN {
-            let d_hidden = err * self.nn_w2[bit_pos][j] * hidden[j] * (1.0 - hidden[j]);
-            for i in 0..N_MODELS {
-                self.nn_w1[bit_pos][j][i] =
-                    (self.nn_w1[bit_pos][j][i] + NN_LR * d_hidden * stretched[i])
-                        .max(-4.0)
-                        .min(4.0);
+        if self.residual {
+            let v = moments.as_mut().map(|m| &mut m.nn_w2[bit_pos][..]);
+            train(&mut self.nn_w2[bit_pos], hidden, nn_lr, err, 4.0, v);
+            let v = moments.as_mut().map(|m| std::slice::from_mut(&mut m.nn_b2[bit_pos]));
+            train(std::slice::from_mut(&mut self.nn_b2[bit_pos]), &[1.0], nn_lr, err, 4.0, v);
+
+            let mut d_hidden = [0.0f64; HIDDEN];
+            for j in 0..HIDDEN {
+                d_hidden[j] = err * self.nn_w2[bit_pos][j] * hidden[j] * (1.0 - hidden[j]);
+            }
+            let v = moments.as_mut().map(|m| &mut m.nn_w1[bit_pos][..self.n_in]);
+            train_units(&mut self.nn_w1[bit_pos][..self.n_in], stretched, nn_lr, &d_hidden, 4.0, v);
+            for (j, &d) in d_hidden.iter().enumerate() {
+                let v = moments.as_mut().map(|m| std::slice::from_mut(&mut m.nn_b1[bit_pos][j]));
+                train(std::slice::from_mut(&mut self.nn_b1[bit_pos][j]), &[1.0], nn_lr, d, 4.0, v);
             }
-            self.nn_b1[bit_pos][j] =
-                (self.nn_b1[bit_pos][j] + NN_LR * d_hidden).max(-4.0).min(4.0);
         }
+
+        self.trained += 1;
+        let (fast, slow) = &mut self.err_avg[bit_pos];
+        *fast += ERR_FAST * (err * err - *fast);
+        *slow += ERR_SLOW * (err * err - *slow);
     }
 
-    fn build_cum_cached(&self, order_hashes: &[u32; 7], max_order: usize) -> [f64; 257] {
+    /// Build PPM's prediction for the next byte, with the LZP match
+    /// blended in.
+    fn predict_ppm(&mut self, order_hashes: &[u32], n_active: usize) {
         let match_byte = self.lzp.pred;
         let match_len = self.lzp.pred_len;
-        let mut dist = self.ppm.distribution_f_cached(order_hashes, max_order);
+        let ppm_cum = match &mut self.ppm_bits {
+            PpmBits::Cum(cum) => cum,
+            PpmBits::Tree(tree) => {
+                self.ppm.tree_distribution(&self.hist, order_hashes, n_active, tree);
+                if match_byte >= 0 && match_len >= 4 {
+                    let lzp_w = (match_len as f64 * 0.01).min(0.25);
+                    tree.blend(match_byte as u8, lzp_w, 0.98, 0.02 / 255.0);
+                }
+                tree.sum_up();
+                return;
+            }
+        };
+
+        let mut dist = self.ppm.distribution_f_cached(&self.hist, order_hashes, n_active);
 
         if match_byte >= 0 && match_len >= 4 {
             let lzp_w = (match_len as f64 * 0.01).min(0.25);
             let rest = 0.02 / 255.0;
-            for b in 0..256 {
-                if b as i32 == match_byte {
-                    dist[b] = (1.0 - lzp_w) * dist[b] + lzp_w * 0.98;
-                } else {
-                    dist[b] = (1.0 - lzp_w) * dist[b] + lzp_w * rest;
-                }
+            for (b, p) in dist.iter_mut().enumerate() {
+                let target = if b as i32 == match_byte { 0.98 } else { rest };
+                *p = (1.0 - lzp_w) * *p + lzp_w * target;
             }
         }
 
-        let mut ppm_cum = [0.0f64; 257];
         for i in 0..256 {
             ppm_cum[i + 1] = ppm_cum[i] + dist[i];
         }
-        ppm_cum
     }
 
     #[inline(always)]
     fn gather_preds(
         &self,
+        bit_pos: usize,
         node: u32,
-        ppm_cum: &[f64; 257],
-        hashes: &[u32; N_BIT_MODELS],
-    ) -> [f64; N_MODELS] {
-        let mut preds = [0.5f64; N_MODELS];
-        preds[0] = Self::ppm_bit_prob(ppm_cum, node);
-        for m in 0..N_BIT_MODELS {
-            preds[1 + m] = self.bit_predict_direct(hashes[m]);
+        slots: &[usize; MAX_BIT_MODELS],
+    ) -> ([f64; MAX_MODELS], [Option<MatchSlot>; MAX_MATCH_MODELS]) {
+        let mut preds = [0.5f64; MAX_MODELS];
+        preds[0] = self.ppm_bits.p1(node);
+        for m in 0..self.n_bit {
+            preds[1 + m] = self.bit_table.predict(m, slots[m]);
+        }
+        let mut match_slots = [None; MAX_MATCH_MODELS];
+        for (j, mm) in self.matches.iter().enumerate() {
+            let (p, slot) = mm.predict(&self.hist, bit_pos, node);
+            preds[1 + self.n_bit + j] = p;
+            match_slots[j] = slot;
         }
-        preds
+        (preds, match_slots)
     }
 
-    pub fn encode_byte(&mut self, byte: u8, enc: &mut AEnc) {
+    /// Mix all model predictions for one bit and refine with SSE.
+    #[inline(always)]
+    fn predict_bit(
+        &mut self,
+        bit_pos: usize,
+        node: u32,
+        slots: &[usize; MAX_BIT_MODELS],
+    ) -> BitPrediction {
+        let (preds, match_slots) = self.gather_preds(bit_pos, node, slots);
+        let rows = self.select_rows(bit_pos, node);
+        if let Some(p1) = self.predict_fixed(bit_pos, node, &preds, &rows) {
+            return BitPrediction::fixed(p1, match_slots);
+        }
+        let (mixed, stretched, hidden, dots) = self.forward(bit_pos, &preds, &rows);
+
+        // SSE refinement
+        let bin_f = mixed * (SSE_BINS - 1) as f64;
+        let bin = (bin_f as usize).min(SSE_BINS - 2);
+        let frac = bin_f - bin as f64;
+        let sse_p = self.sse[bit_pos][bin] * (1.0 - frac) + self.sse[bit_pos][bin + 1] * frac;
+
+        let mut apm_slots = [ApmSlot::default(); MAX_APMS];
+        let mut stage_x = [0.0f64; 8];
+        let final_p = match &self.stage_mix {
+            None => 0.7 * mixed + 0.3 * sse_p,
+            Some(sm) => {
+                // Each APM refines the previous stage's output
+                let mut probs = [0.0f64; 2 + MAX_APMS];
+                probs[0] = mixed;
+                probs[1] = sse_p;
+                let mut p = sse_p;
+                for (k, apm) in self.apms.iter().enumerate() {
+                    let (q, slot) = apm.refine(p, self.apm_ctx(self.apm_ctxs[k], bit_pos, node));
+                    apm_slots[k] = slot;
+                    probs[2 + k] = q;
+                    p = q;
+                }
+                let (p, xs) = sm.mix(bit_pos, &probs[..2 + self.apms.len()]);
+                stage_x = xs;
+                p
+            }
+        };
+
+        let p1 = match &mut self.final_apm {
+            Some(fa) => fa.refine(fixed::quantize(final_p), bit_pos),
+            None => ((final_p * self.bit_scale as f64).round() as u64).clamp(1, self.bit_scale - 1),
+        };
+        BitPrediction {
+            stretched,
+            hidden,
+            rows,
+            dots,
+            mixed,
+            sse_bin: bin,
+            apm_slots,
+            match_slots,
+            stage_x,
+            final_p,
+            p1,
+        }
+    }
+
+    /// Coder probability from the integer back end, if the mixer uses it.
+    #[inline(always)]
+    fn predict_fixed(
+        &mut self,
+        bit_pos: usize,
+        node: u32,
+        preds: &[f64; MAX_MODELS],
+        rows: &[usize; MAX_SETS],
+    ) -> Option<u64> {
+        self.fixed.as_ref()?;
+        let mut apm_ctx = [0usize; MAX_APMS];
+        for (k, &ctx) in self.apm_ctxs.iter().enumerate() {
+            apm_ctx[k] = self.apm_ctx(ctx, bit_pos, node);
+        }
+        let (n_in, n_apms) = (self.n_in, self.apm_ctxs.len());
+        let fx = self.fixed.as_deref_mut()?;
+        let p = fx.predict(bit_pos, &preds[..n_in], rows, &apm_ctx[..n_apms]);
+        Some(match &mut self.final_apm {
+            Some(fa) => fa.refine(p, bit_pos),
+            None => ((p as u64 * self.bit_scale) >> 16).clamp(1, self.bit_scale - 1),
+        })
+    }
+
+    /// Context of an APM stage for the current bit.
+    #[inline(always)]
+    fn apm_ctx(&self, ctx: ApmCtx, bit_pos: usize, node: u32) -> usize {
         let n = self.hist.len();
-        let max_order = std::cmp::min(self.mo + 1, n + 1);
+        let c1 = if n >= 1 { self.hist.back(1) as u32 } else { 0 };
+        match ctx {
+            ApmCtx::Order1 => ((c1 << 8) | node) as usize,
+            ApmCtx::Order2 => {
+                let c2 = if n >= 2 { self.hist.back(2) as u32 } else { 0 };
+                let h = ((c2 << 8) | c1).wrapping_mul(2654435761) >> (32 - (APM_ORDER2_BITS - 8));
+                ((h << 8) | node) as usize
+            }
+            ApmCtx::Match => {
+                let pred = self.lzp.pred;
+                if pred < 0 || ((pred as u32) | 256) >> (8 - bit_pos) != node {
+                    return node as usize;
+                }
+                let len = self.lzp.pred_len.min(31) as usize;
+                let expected = ((pred as u32 >> (7 - bit_pos)) & 1) as usize;
+                256 + (len * 2 + expected) * 8 + bit_pos
+            }
+        }
+    }
 
-        // Extract order hashes for PPM (shared computation)
-        let mut order_hashes = [0u32; 7];
-        for order in 0..max_order {
-            order_hashes[order] = if order == 0 {
-                0u32
-            } else {
-                fnv(&self.hist, n - order, n)
-            };
+    #[inline(always)]
+    fn update_bit(&mut self, bit_pos: usize, pr: &BitPrediction, slots: &[usize; MAX_BIT_MODELS], bit: u8) {
+        if let Some(fx) = &mut self.fixed {
+            fx.update(bit);
+        } else {
+            self.update_mix(bit_pos, pr, bit);
+        }
+        if let Some(fa) = &mut self.final_apm {
+            fa.update(bit);
+        }
+
+        for (m, &slot) in slots[..self.n_bit].iter().enumerate() {
+            self.bit_table.update(m, slot, bit);
+        }
+        for (j, mm) in self.matches.iter_mut().enumerate() {
+            mm.update_bit(pr.match_slots[j], bit);
+        }
+    }
+
+    /// Train the floating-point mixer, NN, SSE and APM stages.
+    #[inline(always)]
+    fn update_mix(&mut self, bit_pos: usize, pr: &BitPrediction, bit: u8) {
+        self.backward(bit_pos, pr, bit as f64);
+
+        // SSE update
+        let target = bit as f64;
+        let bin = pr.sse_bin;
+        self.sse[bit_pos][bin] += SSE_RATE * (target - self.sse[bit_pos][bin]);
+        self.sse[bit_pos][bin + 1] += SSE_RATE * (target - self.sse[bit_pos][bin + 1]);
+
+        if let Some(sm) = &mut self.stage_mix {
+            for (k, apm) in self.apms.iter_mut().enumerate() {
+                apm.update(pr.apm_slots[k], bit);
+            }
+            sm.update(bit_pos, &pr.stage_x, pr.final_p, bit);
         }
+    }
 
-        let ppm_cum = self.build_cum_cached(&order_hashes, max_order);
+    pub fn encode_byte(&mut self, byte: u8, enc: &mut Encoder) {
+        // Order hashes for PPM (shared computation)
+        let (order_hashes, n_ppm) = self.ppm.order_hashes(&self.hist);
+        self.predict_ppm(&order_hashes, n_ppm);
 
         // Precompute byte-level hashes once (constant across all 8 bits)
-        let (byte_bases, active) = self.precompute_byte_hashes(n, max_order);
+        let (byte_bases, active) = self.precompute_byte_hashes();
 
         let mut node: u32 = 1;
         for bit_pos in 0..8 {
             let bit = (byte >> (7 - bit_pos)) & 1;
-            let hashes = Self::make_bit_hashes(&byte_bases, &active, node);
-            let preds = self.gather_preds(node, &ppm_cum, &hashes);
-
-            let (mixed, stretched, hidden) = self.forward(bit_pos, &preds);
-
-            // SSE refinement
-            let bin_f = mixed * (SSE_BINS - 1) as f64;
-            let bin = (bin_f as usize).min(SSE_BINS - 2);
-            let frac = bin_f - bin as f64;
-            let sse_p = self.sse[bit_pos][bin] * (1.0 - frac) + self.sse[bit_pos][bin + 1] * frac;
-            let final_p = 0.7 * mixed + 0.3 * sse_p;
-
-            let p1 = (final_p * BIT_SCALE as f64).round() as u64;
-            let p1 = p1.max(1).min(BIT_SCALE - 1);
-            enc.encode_bit(bit, p1, BIT_SCALE);
-
-            self.backward(bit_pos, &stretched, &hidden, mixed, bit as f64);
-
-            // SSE update
-            let target = bit as f64;
-            self.sse[bit_pos][bin] += SSE_RATE * (target - self.sse[bit_pos][bin]);
-            self.sse[bit_pos][bin + 1] += SSE_RATE * (target - self.sse[bit_pos][bin + 1]);
-
-            for m in 0..N_BIT_MODELS {
-                self.bit_update_direct(hashes[m], bit as usize);
-            }
+            let mut slots = [0usize; MAX_BIT_MODELS];
+            self.bit_table.slots(&byte_bases, &active, node, bit_pos, &mut slots[..self.n_bit]);
+            let pr = self.predict_bit(bit_pos, node, &slots);
+            enc.encode_bit(bit, pr.p1, self.bit_scale);
+            self.update_bit(bit_pos, &pr, &slots, bit);
             node = node * 2 + bit as u32;
         }
-        self.ppm.update_cached(byte, &order_hashes, max_order);
-        self.lzp.update(byte);
-        self.update_word_hash(byte);
-        self.hist.push(byte);
+        self.ppm.update_cached(&self.hist, byte, &order_hashes, n_ppm);
+        self.advance(byte);
+        self.lzp.update(&self.hist);
     }
 
-    pub fn decode_byte(&mut self, dec: &mut ADec) -> u8 {
-        let n = self.hist.len();
-        let max_order = std::cmp::min(self.mo + 1, n + 1);
-
-        // Extract order hashes for PPM (shared computation)
-        let mut order_hashes = [0u32; 7];
-        for order in 0..max_order {
-            order_hashes[order] = if order == 0 {
-                0u32
-            } else {
-                fnv(&self.hist, n - order, n)
-            };
-        }
-
-        let ppm_cum = self.build_cum_cached(&order_hashes, max_order);
+    pub fn decode_byte(&mut self, dec: &mut Decoder) -> u8 {
+        // Order hashes for PPM (shared computation)
+        let (order_hashes, n_ppm) = self.ppm.order_hashes(&self.hist);
+        self.predict_ppm(&order_hashes, n_ppm);
 
         // Precompute byte-level hashes once
-        let (byte_bases, active) = self.precompute_byte_hashes(n, max_order);
+        let (byte_bases, active) = self.precompute_byte_hashes();
 
         let mut node: u32 = 1;
         let mut byte_val: u8 = 0;
         for bit_pos in 0..8 {
-            let hashes = Self::make_bit_hashes(&byte_bases, &active, node);
-            let preds = self.gather_preds(node, &ppm_cum, &hashes);
-
-            let (mixed, stretched, hidden) = self.forward(bit_pos, &preds);
-
-            // SSE refinement
-            let bin_f = mixed * (SSE_BINS - 1) as f64;
-            let bin = (bin_f as usize).min(SSE_BINS - 2);
-            let frac = bin_f - bin as f64;
-            let sse_p = self.sse[bit_pos][bin] * (1.0 - frac) + self.sse[bit_pos][bin + 1] * frac;
-            let final_p = 0.7 * mixed + 0.3 * sse_p;
-
-            let p1 = (final_p * BIT_SCALE as f64).round() as u64;
-            let p1 = p1.max(1).min(BIT_SCALE - 1);
-            let bit = dec.decode_bit(p1, BIT_SCALE);
-
-            self.backward(bit_pos, &stretched, &hidden, mixed, bit as f64);
-
-            // SSE update
-            let target = bit as f64;
-            self.sse[bit_pos][bin] += SSE_RATE * (target - self.sse[bit_pos][bin]);
-            self.sse[bit_pos][bin + 1] += SSE_RATE * (target - self.sse[bit_pos][bin + 1]);
-
-            for m in 0..N_BIT_MODELS {
-                self.bit_update_direct(hashes[m], bit as usize);
-            }
+            let mut slots = [0usize; MAX_BIT_MODELS];
+            self.bit_table.slots(&byte_bases, &active, node, bit_pos, &mut slots[..self.n_bit]);
+            let pr = self.predict_bit(bit_pos, node, &slots);
+            let bit = dec.decode_bit(pr.p1, self.bit_scale);
+            self.update_bit(bit_pos, &pr, &slots, bit);
             byte_val = (byte_val << 1) | bit;
             node = node * 2 + bit as u32;
         }
-        self.ppm.update_cached(byte_val, &order_hashes, max_order);
-        self.lzp.update(byte_val);
-        self.update_word_hash(byte_val);
-        self.hist.push(byte_val);
+        self.ppm.update_cached(&self.hist, byte_val, &order_hashes, n_ppm);
+        self.advance(byte_val);
+        self.lzp.update(&self.hist);
         byte_val
     }
 }
@@ -1,83 +1,113 @@
-use crate::arithmetic::{AEnc, ADec};
+use crate::arithmetic::{Decoder, Encoder};
 use crate::charfreq::CHAR_FREQ;
-use crate::fnv::fnv;
+use crate::config::{ModelConfig, PPM_ADAPTIVE, PPM_ESCAPE, PPM_RESTART};
+use crate::history::History;
 
 const MAX_ORD: usize = 6;
+/// Most context orders a PPM model can interpolate over
+pub const MAX_PPM_ORDERS: usize = 16;
 const DISCOUNT: f64 = 0.85;
+/// Share of the order -1 distribution mixed into every interpolated one
+const KN_FLOOR: f64 = 0.10;
+/// Previous-byte classes with their own order -1 bias
+const BASE_CLASSES: usize = 9;
+/// Smallest adaptive discount, keeping every order's escape mass positive
+const MIN_DISCOUNT: f64 = 0.05;
 
-// ── Open-addressing hash table: u32 -> SymCounts ──
+// ── Open-addressing hash table: u32 -> symbol counts ──
 
 const EMPTY_KEY: u32 = u32::MAX;
+/// Block capacities run 1, 2, 4 .. 256 symbols
+const SIZE_CLASSES: usize = 9;
 
-/// Compact symbol-count map using flat Vec for cache-friendly iteration.
-#[derive(Clone)]
-struct SymCounts {
-    entries: Vec<(u8, u32)>,
+/// Where a context's symbols live in its table's arena. The total and the
+/// number of symbols are kept up to date so lookups never sum the counts.
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    start: u32,
+    len: u16,
+    /// log2 of the block capacity, meaningless while `len` is 0
+    class: u8,
+    total: u32,
 }
 
-impl SymCounts {
-    #[inline]
-    fn new() -> Self {
-        Self {
-            entries: Vec::new(), // zero alloc until first push
-        }
-    }
-
-    #[inline]
-    fn increment(&mut self, sym: u8) {
-        for entry in self.entries.iter_mut() {
-            if entry.0 == sym {
-                entry.1 += 1;
-                return;
-            }
-        }
-        self.entries.push((sym, 1));
-    }
-
-    #[inline]
-    fn total(&self) -> u32 {
-        let mut s = 0u32;
-        for &(_, c) in &self.entries {
-            s += c;
-        }
-        s
-    }
+/// Symbol counts of one context, borrowed from a table's arena.
+struct SymCounts<'a> {
+    syms: &'a [u8],
+    counts: &'a [u32],
+    total: u32,
+}
 
+impl SymCounts<'_> {
     #[inline]
     fn len(&self) -> usize {
-        self.entries.len()
+        self.syms.len()
     }
 
     #[inline]
-    fn iter(&self) -> std::slice::Iter<(u8, u32)> {
-        self.entries.iter()
+    fn iter(&self) -> impl Iterator<Item = (u8, u32)> + '_ {
+        self.syms.iter().copied().zip(self.counts.iter().copied())
     }
+}
 
-    #[inline]
-    fn values_mut(&mut self) -> impl Iterator<Item = &mut u32> {
-        self.entries.iter_mut().map(|(_, c)| c)
+/// Position of `sym` in `syms`, eight symbols per step: a zero byte of
+/// `word ^ splat(sym)` marks a match, and the lowest flagged byte is
+/// always a true one.
+#[inline]
+fn find_sym(syms: &[u8], sym: u8) -> Option<usize> {
+    const LOW: u64 = 0x0101_0101_0101_0101;
+    let chunks = syms.chunks_exact(8);
+    let tail = chunks.remainder();
+    for (i, chunk) in chunks.enumerate() {
+        let x = u64::from_le_bytes(chunk.try_into().unwrap()) ^ (LOW * sym as u64);
+        let zero = x.wrapping_sub(LOW) & !x & (LOW << 7);
+        if zero != 0 {
+            return Some(i * 8 + zero.trailing_zeros() as usize / 8);
+        }
     }
+    tail.iter().position(|&s| s == sym).map(|i| syms.len() - tail.len() + i)
 }
 
-/// Open-addressing hash table with linear probing. u32 keys -> SymCounts values.
-/// Keys and values in separate arrays: probing only touches the keys array
-/// (4 bytes each, 16 keys per cache line), values accessed only on hit.
+/// Open-addressing hash table with linear probing. u32 keys -> symbol counts.
+/// Keys and slots in separate arrays: probing only touches the keys array
+/// (4 bytes each, 16 keys per cache line), slots accessed only on hit.
+///
+/// Symbols and counts of all contexts share one arena, in power-of-two
+/// blocks. A full block moves to one twice its size and is kept on a free
+/// list for the next context that needs that size. The arena reserves its
+/// own room, a quarter more at a time, so what it holds is known exactly.
 #[derive(Clone)]
 struct CtxTable {
     keys: Vec<u32>,
-    vals: Vec<SymCounts>,
+    slots: Vec<Slot>,
     mask: usize,
     len: usize,
+    syms: Vec<u8>,
+    counts: Vec<u32>,
+    /// Arena entries allocated, used or not
+    reserved: usize,
+    /// Released block starts per size class
+    free: [Vec<u32>; SIZE_CLASSES],
+    /// coc[k]: symbol entries with a count of exactly k, for k in 1..=4
+    coc: [u32; 5],
 }
 
 impl CtxTable {
     fn new() -> Self {
-        let cap = 64; // small initial size, grows as needed
+        Self::with_capacity(64) // small initial size, grows as needed
+    }
+
+    fn with_capacity(cap: usize) -> Self {
         Self {
             keys: vec![EMPTY_KEY; cap],
-            vals: (0..cap).map(|_| SymCounts::new()).collect(),
+            slots: vec![Slot::default(); cap],
             mask: cap - 1,
             len: 0,
+            syms: Vec::new(),
+            counts: Vec::new(),
+            reserved: 0,
+            free: Default::default(),
+            coc: [0; 5],
         }
     }
 
@@ -86,14 +116,24 @@ impl CtxTable {
         if key == EMPTY_KEY { key.wrapping_sub(1) } else { key }
     }
 
+    #[inline(always)]
+    fn counts_at(&self, slot: &Slot) -> SymCounts<'_> {
+        let range = slot.start as usize..slot.start as usize + slot.len as usize;
+        SymCounts {
+            syms: &self.syms[range.clone()],
+            counts: &self.counts[range],
+            total: slot.total,
+        }
+    }
+
     #[inline]
-    fn get(&self, key: u32) -> Option<&SymCounts> {
+    fn get(&self, key: u32) -> Option<SymCounts<'_>> {
         let key = Self::fix_key(key);
         let mut idx = (key as usize) & self.mask;
         loop {
             let k = unsafe { *self.keys.get_unchecked(idx) };
             if k == key {
-                return Some(unsafe { self.vals.get_unchecked(idx) });
+                return Some(self.counts_at(unsafe { self.slots.get_unchecked(idx) }));
             }
             if k == EMPTY_KEY {
                 return None;
@@ -102,32 +142,125 @@ impl CtxTable {
         }
     }
 
+    /// Slot index for `key`, inserting an empty context if it is missing.
     #[inline]
-    fn get_or_insert(&mut self, key: u32) -> &mut SymCounts {
+    fn find_or_insert(&mut self, key: u32) -> usize {
         let key = Self::fix_key(key);
-        if self.len * 2 >= self.mask + 1 {
+        if self.len * 2 > self.mask {
             self.grow();
         }
         let mut idx = (key as usize) & self.mask;
         loop {
             let k = unsafe { *self.keys.get_unchecked(idx) };
             if k == key {
-                return unsafe { self.vals.get_unchecked_mut(idx) };
+                return idx;
             }
             if k == EMPTY_KEY {
                 unsafe { *self.keys.get_unchecked_mut(idx) = key; }
                 self.len += 1;
-                return unsafe { self.vals.get_unchecked_mut(idx) };
+                return idx;
             }
             idx = (idx + 1) & self.mask;
         }
     }
 
+    /// Count `sym` in the context `key`, halving the context's counts once
+    /// their total reaches `halve_at`; true if `sym` is new to that context.
+    #[inline]
+    fn increment(&mut self, key: u32, sym: u8, halve_at: u32) -> bool {
+        let idx = self.find_or_insert(key);
+        let new = self.count(idx, sym);
+        if self.slots[idx].total >= halve_at {
+            self.halve(idx);
+        }
+        new
+    }
+
+    #[inline]
+    fn count(&mut self, idx: usize, sym: u8) -> bool {
+        let slot = self.slots[idx];
+        let start = slot.start as usize;
+        let len = slot.len as usize;
+        if let Some(i) = find_sym(&self.syms[start..start + len], sym) {
+            let count = self.counts[start + i] as usize;
+            self.counts[start + i] += 1;
+            if count <= 4 {
+                self.coc[count] -= 1;
+            }
+            if count < 4 {
+                self.coc[count + 1] += 1;
+            }
+            self.slots[idx].total += 1;
+            return false;
+        }
+
+        let mut slot = slot;
+        if len == 0 {
+            slot.class = 0;
+            slot.start = self.alloc(0);
+        } else if len == 1 << slot.class {
+            let to = self.alloc(slot.class + 1);
+            self.syms.copy_within(start..start + len, to as usize);
+            self.counts.copy_within(start..start + len, to as usize);
+            self.free[slot.class as usize].push(slot.start);
+            slot.class += 1;
+            slot.start = to;
+        }
+        let at = slot.start as usize + len;
+        self.syms[at] = sym;
+        self.counts[at] = 1;
+        self.coc[1] += 1;
+        slot.len += 1;
+        slot.total += 1;
+        self.slots[idx] = slot;
+        true
+    }
+
+    /// Halve the counts of the context in slot `idx`, rounding up so no
+    /// symbol is forgotten.
+    fn halve(&mut self, idx: usize) {
+        let slot = &mut self.slots[idx];
+        let start = slot.start as usize;
+        slot.total = 0;
+        for count in &mut self.counts[start..start + slot.len as usize] {
+            if *count <= 4 {
+                self.coc[*count as usize] -= 1;
+            }
+            *count = count.div_ceil(2);
+            if *count <= 4 {
+                self.coc[*count as usize] += 1;
+            }
+            slot.total += *count;
+        }
+    }
+
+    /// Start of a free block of `1 << class` entries.
+    fn alloc(&mut self, class: u8) -> u32 {
+        if let Some(start) = self.free[class as usize].pop() {
+            return start;
+        }
+        let start = self.syms.len();
+        let end = start + (1 << class);
+        if end > self.reserved {
+            self.reserve(end.max(self.reserved + self.reserved / ARENA_GROWTH_DIV));
+        }
+        self.syms.resize(end, 0);
+        self.counts.resize(end, 0);
+        start as u32
+    }
+
+    /// Grow the arena's allocation to exactly `entries`.
+    fn reserve(&mut self, entries: usize) {
+        self.syms.reserve_exact(entries - self.syms.len());
+        self.counts.reserve_exact(entries - self.counts.len());
+        self.reserved = entries;
+    }
+
     fn grow(&mut self) {
         let new_cap = (self.mask + 1) * 2;
         let new_mask = new_cap - 1;
         let mut new_keys = vec![EMPTY_KEY; new_cap];
-        let mut new_vals: Vec<SymCounts> = (0..new_cap).map(|_| SymCounts::new()).collect();
+        let mut new_slots = vec![Slot::default(); new_cap];
 
         for old_idx in 0..=self.mask {
             if self.keys[old_idx] != EMPTY_KEY {
@@ -136,7 +269,7 @@ impl CtxTable {
                 loop {
                     if new_keys[idx] == EMPTY_KEY {
                         new_keys[idx] = k;
-                        std::mem::swap(&mut new_vals[idx], &mut self.vals[old_idx]);
+                        new_slots[idx] = self.slots[old_idx];
                         break;
                     }
                     idx = (idx + 1) & new_mask;
@@ -145,43 +278,300 @@ impl CtxTable {
         }
 
         self.keys = new_keys;
-        self.vals = new_vals;
+        self.slots = new_slots;
         self.mask = new_mask;
     }
 
-    fn values_mut(&mut self) -> impl Iterator<Item = &mut SymCounts> {
-        self.keys
+    /// Replace every count with `f(count)`, keeping the cached totals and
+    /// count-of-counts.
+    fn map_counts(&mut self, f: impl Fn(u32) -> u32) {
+        self.coc = [0; 5];
+        for (k, slot) in self.keys.iter().zip(self.slots.iter_mut()) {
+            if *k == EMPTY_KEY {
+                continue;
+            }
+            let start = slot.start as usize;
+            let counts = &mut self.counts[start..start + slot.len as usize];
+            slot.total = 0;
+            for count in counts {
+                *count = f(*count);
+                slot.total += *count;
+                if *count <= 4 {
+                    self.coc[*count as usize] += 1;
+                }
+            }
+        }
+    }
+
+    /// Modified Kneser-Ney discounts for counts of 1, 2 and 3 or more,
+    /// estimated from the count-of-counts (Chen & Goodman). `DISCOUNT`
+    /// until every count up to 4 has been seen.
+    fn discounts(&self) -> [f64; 3] {
+        let [_, n1, n2, n3, n4] = self.coc.map(|n| n as f64);
+        if n1 == 0.0 || n2 == 0.0 || n3 == 0.0 || n4 == 0.0 {
+            return [DISCOUNT; 3];
+        }
+        let y = n1 / (n1 + 2.0 * n2);
+        [
+            (1.0 - 2.0 * y * n2 / n1).clamp(MIN_DISCOUNT, 1.0 - MIN_DISCOUNT),
+            (2.0 - 3.0 * y * n3 / n2).clamp(MIN_DISCOUNT, 2.0 - MIN_DISCOUNT),
+            (3.0 - 4.0 * y * n4 / n3).clamp(MIN_DISCOUNT, 3.0 - MIN_DISCOUNT),
+        ]
+    }
+
+    /// Bytes held by the key and slot arrays, the whole arena including
+    /// free blocks and unused room, and the free lists.
+    fn bytes(&self) -> usize {
+        let slots = (self.mask + 1) * (size_of::<u32>() + size_of::<Slot>());
+        let arena = self.reserved * (size_of::<u8>() + size_of::<u32>());
+        let free = self.free.iter().map(Vec::len).sum::<usize>() * size_of::<u32>();
+        slots + arena + free
+    }
+
+    /// Keep only contexts whose total count exceeds `min_total`, shrinking the
+    /// table to fit them and compacting the arena. Returns the symbol entries left.
+    fn prune(&mut self, min_total: u32) -> usize {
+        let kept: Vec<(u32, Slot)> = self
+            .keys
             .iter()
-            .zip(self.vals.iter_mut())
-            .filter(|(&k, _)| k != EMPTY_KEY)
-            .map(|(_, v)| v)
+            .zip(&self.slots)
+            .filter(|(&k, slot)| k != EMPTY_KEY && slot.total > min_total)
+            .map(|(&k, &slot)| (k, slot))
+            .collect();
+        let cap = (kept.len() * 2 + 2).next_power_of_two().max(64);
+        let mut table = Self::with_capacity(cap);
+        table.len = kept.len();
+        table.reserve(kept.iter().map(|(_, slot)| (slot.len as usize).next_power_of_two()).sum());
+        let mut syms = 0;
+        for (k, slot) in kept {
+            let mut idx = (k as usize) & table.mask;
+            while table.keys[idx] != EMPTY_KEY {
+                idx = (idx + 1) & table.mask;
+            }
+            let len = slot.len as usize;
+            let class = len.next_power_of_two().trailing_zeros() as u8;
+            let start = table.alloc(class);
+            let from = slot.start as usize..slot.start as usize + len;
+            table.syms[start as usize..start as usize + len].copy_from_slice(&self.syms[from.clone()]);
+            table.counts[start as usize..start as usize + len].copy_from_slice(&self.counts[from]);
+            for &count in &table.counts[start as usize..start as usize + len] {
+                if count <= 4 {
+                    table.coc[count as usize] += 1;
+                }
+            }
+            syms += len;
+            table.keys[idx] = k;
+            table.slots[idx] = Slot { start, class, ..slot };
+        }
+        *self = table;
+        syms
+    }
+}
+
+/// What PPM does once its tables outgrow the memory budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrunePolicy {
+    /// Drop contexts seen rarely, raising the cut-off until well under budget
+    LowCounts,
+    /// Start over and re-prime from the most recent history
+    Restart,
+}
+
+/// A full arena grows by this fraction of its size
+const ARENA_GROWTH_DIV: usize = 4;
+/// Pruning aims this far under the budget so it does not run every byte
+const PRUNE_TARGET_NUM: usize = 3;
+const PRUNE_TARGET_DEN: usize = 4;
+/// History replayed after a restart, as a divisor of the budget
+const REPRIME_DIV: usize = 256;
+
+// ── Secondary escape estimation ──
+
+/// Share of the order -1 distribution mixed into every escape-coded
+/// distribution, so no byte is ever impossible
+const ESCAPE_FLOOR: f64 = 0.002;
+const SEE_UNIQUE_BUCKETS: usize = 8;
+const SEE_TOTAL_BUCKETS: usize = 8;
+const SEE_CELLS: usize = MAX_PPM_ORDERS * SEE_UNIQUE_BUCKETS * SEE_TOTAL_BUCKETS * 2;
+const SEE_LIMIT: u32 = 255;
+
+/// Count and number of the symbols of `d` not excluded by a longer order.
+#[inline]
+fn escape_stats(d: &SymCounts, excluded: &[bool; 256]) -> (u32, u32) {
+    let mut total = 0;
+    let mut unique = 0;
+    for (sym, count) in d.iter() {
+        if !excluded[sym as usize] {
+            total += count;
+            unique += 1;
+        }
+    }
+    (total, unique)
+}
+
+/// Secondary escape estimation: learned escape probabilities per (order,
+/// distinct symbols, total count, whether a longer order was consulted).
+/// A cell starts from the PPMC estimate `unique / (total + unique)` and
+/// then follows what actually escaped from contexts like it.
+#[derive(Clone)]
+struct See {
+    /// (escape probability, observations) per cell
+    cells: Vec<(f64, u32)>,
+}
+
+impl See {
+    fn new() -> Self {
+        Self { cells: vec![(0.0, 0); SEE_CELLS] }
+    }
+
+    fn context(order: usize, unique: u32, total: u32, seen: usize) -> usize {
+        let unique = match unique {
+            1..=4 => unique as usize - 1,
+            5..=6 => 4,
+            7..=10 => 5,
+            11..=20 => 6,
+            _ => 7,
+        };
+        let total = (total.ilog2() as usize).min(SEE_TOTAL_BUCKETS - 1);
+        ((order * SEE_UNIQUE_BUCKETS + unique) * SEE_TOTAL_BUCKETS + total) * 2 + (seen > 0) as usize
+    }
+
+    #[inline]
+    fn escape(&self, ctx: usize, unique: u32, total: u32) -> f64 {
+        match self.cells[ctx] {
+            (_, 0) => unique as f64 / (total + unique) as f64,
+            (p, _) => p,
+        }
+    }
+
+    fn update(&mut self, ctx: usize, unique: u32, total: u32, escaped: bool) {
+        let p = self.escape(ctx, unique, total);
+        let (cell, n) = &mut self.cells[ctx];
+        let hit = escaped as u32 as f64;
+        *cell = (p + (hit - p) / (*n as f64 + 1.5)).clamp(1.0 / 1024.0, 1023.0 / 1024.0);
+        *n = (*n + 1).min(SEE_LIMIT);
+    }
+}
+
+// ── Bit-tree view of a distribution ──
+
+/// A byte distribution as weighted order -1 counts, a flat weight and
+/// per-byte terms, summed up the bit tree once so each bit's probability is
+/// a single division. Building it touches only the symbols the contexts
+/// hold, instead of rescaling a 256-entry distribution per order.
+#[derive(Clone)]
+pub(crate) struct TreeDist {
+    /// Order -1 counts and the weight of one count
+    base: [u32; 256],
+    base_w: f64,
+    /// Weight every byte gets
+    flat: f64,
+    terms: [f64; 256],
+    /// sums[node]: weight under each node of the bit tree (root 1, byte b at
+    /// 256 + b), filled in by `sum_up`
+    sums: [f64; 512],
+}
+
+impl TreeDist {
+    pub(crate) fn new() -> Self {
+        Self {
+            base: [0; 256],
+            base_w: 0.0,
+            flat: 0.0,
+            terms: [0.0; 256],
+            sums: [0.0; 512],
+        }
+    }
+
+    /// Start over from the order -1 counts `base` at zero weight.
+    fn reset(&mut self, base: &[u32; 256]) {
+        self.base = *base;
+        self.base_w = 0.0;
+        self.flat = 0.0;
+        self.terms = [0.0; 256];
+    }
+
+    /// Mix in `w` of a distribution giving `hit` to `byte` and `miss` to
+    /// every other byte.
+    pub(crate) fn blend(&mut self, byte: u8, w: f64, hit: f64, miss: f64) {
+        self.base_w *= 1.0 - w;
+        self.flat = self.flat * (1.0 - w) + w * miss;
+        for t in self.terms.iter_mut() {
+            *t *= 1.0 - w;
+        }
+        self.terms[byte as usize] += w * (hit - miss);
+    }
+
+    /// Fill in the node sums; call once the distribution is complete.
+    pub(crate) fn sum_up(&mut self) {
+        for b in 0..256 {
+            // Excluded bytes cancel to about zero, possibly a hair below
+            let p = self.terms[b] + self.base_w * self.base[b] as f64 + self.flat;
+            self.sums[256 + b] = p.max(0.0);
+        }
+        for node in (1..256).rev() {
+            self.sums[node] = self.sums[2 * node] + self.sums[2 * node + 1];
+        }
+    }
+
+    /// Probability that the bit below `node` is 1.
+    #[inline(always)]
+    pub(crate) fn p1(&self, node: u32) -> f64 {
+        let node = node as usize;
+        let total = self.sums[node];
+        if total > 1e-10 {
+            self.sums[2 * node + 1] / total
+        } else {
+            0.5
+        }
     }
 }
 
 // ── PPM Model ──
 
-/// PPM model with Kneser-Ney smoothing (no escapes — all orders interpolated).
+/// PPM model with Kneser-Ney smoothing (no escapes — all orders interpolated),
+/// or PPMd-style escape coding with exclusion and SEE (`with_escapes`).
+///
+/// Works over an ascending list of context orders, which need not be
+/// contiguous. Per-byte order hashes are indexed like that list.
 #[derive(Clone)]
 pub struct PPM {
-    mo: usize,
-    /// ctx[order] maps context_hash -> symbol counts
+    orders: Vec<usize>,
+    /// ctx[i] maps context_hash -> symbol counts for orders[i]
     ctx: Vec<CtxTable>,
-    hist: Vec<u8>,
-    /// Set after pretrain: unigram frequencies from training data
-    base_freq: Option<[u32; 256]>,
+    /// Symbol entries over all tables, for the memory estimate
+    syms: usize,
+    /// Memory budget in bytes and the policy applied when it is exceeded
+    budget: Option<(usize, PrunePolicy)>,
+    /// Order -1 counts per previous-byte class, from the pretrain unigram
+    /// frequencies once pretrained (`CHAR_FREQ` before)
+    bases: Vec<([u32; 256], f64)>,
+    /// Escape estimator of the escape-coded variant; `None` interpolates
+    see: Option<See>,
+    /// Interpolate with per-order modified KN discounts instead of `DISCOUNT`
+    adaptive: bool,
+    /// Context total at which its counts are halved (`u32::MAX`: never)
+    halve_at: u32,
 }
 
 impl PPM {
     pub fn new(max_order: usize) -> Self {
-        let mut ctx = Vec::with_capacity(max_order + 1);
-        for _ in 0..=max_order {
-            ctx.push(CtxTable::new());
-        }
+        let orders: Vec<u8> = (0..=max_order as u8).collect();
+        Self::with_orders(&orders)
+    }
+
+    /// PPM over an ascending list of context orders.
+    pub fn with_orders(orders: &[u8]) -> Self {
+        assert!(orders.len() <= MAX_PPM_ORDERS, "too many PPM orders");
         Self {
-            mo: max_order,
-            ctx,
-            hist: Vec::new(),
-            base_freq: None,
+            orders: orders.iter().map(|&o| o as usize).collect(),
+            ctx: orders.iter().map(|_| CtxTable::new()).collect(),
+            syms: 0,
+            budget: None,
+            bases: Self::base_mixes(&CHAR_FREQ),
+            see: None,
+            adaptive: false,
+            halve_at: u32::MAX,
         }
     }
 
@@ -189,198 +579,395 @@ impl PPM {
         Self::new(MAX_ORD)
     }
 
-    fn hash(&self, order: usize) -> Option<u32> {
-        let n = self.hist.len();
-        if order > n {
-            return None;
+    /// PPM with the orders, budget and variant a configuration asks for.
+    pub fn with_config(cfg: &ModelConfig) -> Self {
+        let mut ppm = Self::with_orders(&cfg.effective_ppm_orders());
+        if cfg.ppm_budget != 0 {
+            let policy = match cfg.ppm_policy {
+                PPM_RESTART => PrunePolicy::Restart,
+                _ => PrunePolicy::LowCounts,
+            };
+            ppm = ppm.with_budget(1 << cfg.ppm_budget, policy);
+        }
+        if cfg.ppm_halve != 0 {
+            ppm = ppm.with_halving(1 << cfg.ppm_halve);
         }
-        if order == 0 {
-            return Some(0);
+        match cfg.ppm_model {
+            PPM_ESCAPE => ppm = ppm.with_escapes(),
+            PPM_ADAPTIVE => ppm = ppm.with_adaptive_discounts(),
+            _ => {}
         }
-        Some(fnv(&self.hist, n - order, n))
+        ppm
     }
 
-    pub(crate) fn update(&mut self, byte: u8) {
-        for order in 0..=self.mo {
-            let h = match self.hash(order) {
-                Some(h) => h,
-                None => continue,
-            };
-            let d = self.ctx[order].get_or_insert(h);
-            d.increment(byte);
+    /// Switch to escape coding with full exclusion and SEE in place of
+    /// interpolating every order.
+    pub fn with_escapes(mut self) -> Self {
+        self.see = Some(See::new());
+        self
+    }
+
+    /// Interpolate with discounts estimated per order, and per count of 1, 2
+    /// and 3+, from the counts seen so far.
+    pub fn with_adaptive_discounts(mut self) -> Self {
+        self.adaptive = true;
+        self
+    }
+
+    /// Halve a context's counts whenever their total reaches `limit`, so
+    /// recent statistics outweigh old ones on changing text.
+    pub fn with_halving(mut self, limit: u32) -> Self {
+        self.halve_at = limit;
+        self
+    }
+
+    /// Context orders, for sizing the shared history's suffix hashes.
+    pub fn orders(&self) -> &[usize] {
+        &self.orders
+    }
+
+    /// Limit the estimated table memory to `bytes`, applying `policy` when
+    /// an update goes over. Encoder and decoder must use the same budget.
+    pub fn with_budget(mut self, bytes: usize, policy: PrunePolicy) -> Self {
+        self.budget = Some((bytes, policy));
+        self
+    }
+
+    /// Bytes held by the context tables. Counted from the tables' own
+    /// sizes rather than the allocator's, since where pruning happens is
+    /// part of the format.
+    pub fn memory(&self) -> usize {
+        self.ctx.iter().map(CtxTable::bytes).sum()
+    }
+
+    /// Count `byte` in its contexts; call before pushing it to `hist`.
+    pub fn update(&mut self, hist: &History, byte: u8) {
+        let (hashes, n_active) = self.order_hashes(hist);
+        self.update_cached(hist, byte, &hashes, n_active);
+    }
+
+    /// Hashes of the current contexts, indexed like the order list, and how
+    /// many leading orders are available (orders longer than the history are not).
+    pub(crate) fn order_hashes(&self, hist: &History) -> ([u32; MAX_PPM_ORDERS], usize) {
+        let mut hashes = [0u32; MAX_PPM_ORDERS];
+        let mut active = 0;
+        for (i, &order) in self.orders.iter().enumerate() {
+            if order > hist.len() {
+                break;
+            }
+            hashes[i] = if order == 0 { 0 } else { hist.suffix_hash(order) };
+            active = i + 1;
         }
-        self.hist.push(byte);
+        (hashes, active)
     }
 
     /// Update context tables using pre-computed order hashes.
-    pub(crate) fn update_cached(&mut self, byte: u8, order_hashes: &[u32; 7], max_order: usize) {
-        let limit = std::cmp::min(self.mo + 1, max_order);
-        for order in 0..limit {
-            let h = order_hashes[order];
-            let d = self.ctx[order].get_or_insert(h);
-            d.increment(byte);
+    pub(crate) fn update_cached(&mut self, hist: &History, byte: u8, order_hashes: &[u32], n_active: usize) {
+        self.update_see(byte, order_hashes, n_active);
+        for (ctx, &h) in self.ctx[..n_active].iter_mut().zip(order_hashes) {
+            self.syms += ctx.increment(h, byte, self.halve_at) as usize;
+        }
+        if let Some((budget, policy)) = self.budget {
+            if self.memory() > budget {
+                match policy {
+                    PrunePolicy::LowCounts => self.prune(budget),
+                    PrunePolicy::Restart => self.restart(hist, budget),
+                }
+            }
         }
-        self.hist.push(byte);
     }
 
-    pub fn pretrain(&mut self, data: &[u8]) {
-        for &b in data {
-            self.update(b);
+    fn prune(&mut self, budget: usize) {
+        let target = budget / PRUNE_TARGET_DEN * PRUNE_TARGET_NUM;
+        let mut min_total = 1;
+        loop {
+            self.syms = self.ctx.iter_mut().map(|t| t.prune(min_total)).sum();
+            if self.memory() <= target || self.syms == 0 {
+                break;
+            }
+            min_total *= 2;
+        }
+    }
+
+    /// Empty the tables and count the most recent history again, newest
+    /// byte first, until they fill half the budget.
+    fn restart(&mut self, hist: &History, budget: usize) {
+        for t in self.ctx.iter_mut() {
+            *t = CtxTable::new();
         }
+        self.syms = 0;
+        let n = hist.len();
+        let start = n.saturating_sub(budget / REPRIME_DIV).max(hist.oldest());
+        let mut hashes = [0u32; MAX_PPM_ORDERS];
+        for pos in (start..n).rev() {
+            if self.memory() > budget / 2 {
+                break;
+            }
+            let byte = hist.at(pos);
+            let usable = self.orders.partition_point(|&order| order <= pos - hist.oldest());
+            hist.suffix_hashes_at(pos, &self.orders[..usable], &mut hashes);
+            for ((ctx, &order), &h) in self.ctx[..usable].iter_mut().zip(&self.orders).zip(&hashes) {
+                let h = if order == 0 { 0 } else { h };
+                self.syms += ctx.increment(h, byte, self.halve_at) as usize;
+            }
+        }
+    }
+
+    /// Finish pretraining on `data`, which has been fed through `update`.
+    pub fn finish_pretrain(&mut self, data: &[u8]) {
         // Compute pretrain unigram for order -1 base distribution
         let mut base = [1u32; 256];
         for &b in data {
             base[b as usize] += 2;
         }
-        self.base_freq = Some(base);
+        self.bases = Self::base_mixes(&base);
 
         // Dampen pretrain counts
-        for order in 0..=self.mo {
-            for d in self.ctx[order].values_mut() {
-                for count in d.values_mut() {
-                    *count = std::cmp::max(1, isqrt(*count));
-                }
-            }
+        for table in self.ctx.iter_mut() {
+            table.map_counts(|count| std::cmp::max(1, isqrt(count)));
+        }
+    }
+
+    /// Class of the previous byte picking the order -1 bias.
+    fn prev_class(prev: Option<u8>) -> usize {
+        match prev {
+            Some(129..=255) => 1,
+            Some(b'a'..=b'z') => 2,
+            Some(b' ') => 3,
+            Some(b'.' | b'!' | b'?') => 4,
+            Some(b',') => 5,
+            Some(b'\n') => 6,
+            Some(128) => 7,
+            Some(b'A'..=b'Z') => 8,
+            _ => 0,
         }
     }
 
-    fn class_base(&self) -> [u32; 256] {
+    /// Order -1 bias after a byte of `class`.
+    fn class_bias(class: usize) -> [u32; 256] {
         let mut base = [1u32; 256];
-        let n = self.hist.len();
-        if n == 0 {
-            return base;
-        }
-        let prev = self.hist[n - 1];
-
-        if prev >= 129 {
-            base[32] = 150;
-            base[44] = 40;
-            base[46] = 40;
-            base[39] = 15;
-            base[10] = 15;
-            base[59] = 5;
-            base[58] = 5;
-            base[45] = 8;
-            base[33] = 3;
-            base[63] = 3;
-        } else if (97..=122).contains(&prev) {
-            for b in 97..=122 {
-                base[b] = 40;
-            }
-            base[32] = 120;
-            base[44] = 25;
-            base[46] = 25;
-            base[39] = 15;
-            base[45] = 8;
-            base[10] = 10;
-            for b in 129..=255 {
-                base[b] = 5;
-            }
-        } else if prev == 32 {
-            for b in 129..=255 {
-                base[b] = 60;
-            }
-            base[128] = 40;
-            for b in 97..=122 {
-                base[b] = 25;
-            }
-            for b in 65..=90 {
-                base[b] = 15;
-            }
-            base[34] = 5;
-        } else if prev == 46 || prev == 33 || prev == 63 {
-            base[32] = 200;
-            base[10] = 50;
-        } else if prev == 44 {
-            base[32] = 200;
-        } else if prev == 10 {
-            base[10] = 30;
-            for b in 129..=255 {
-                base[b] = 25;
-            }
-            base[128] = 40;
-            for b in 65..=90 {
-                base[b] = 20;
-            }
-        } else if prev == 128 {
-            for b in 129..=255 {
-                base[b] = 80;
-            }
-        } else if (65..=90).contains(&prev) {
-            for b in 97..=122 {
-                base[b] = 80;
+        match class {
+            1 => {
+                base[32] = 150;
+                base[44] = 40;
+                base[46] = 40;
+                base[39] = 15;
+                base[10] = 15;
+                base[59] = 5;
+                base[58] = 5;
+                base[45] = 8;
+                base[33] = 3;
+                base[63] = 3;
+            }
+            2 => {
+                base[97..=122].fill(40);
+                base[32] = 120;
+                base[44] = 25;
+                base[46] = 25;
+                base[39] = 15;
+                base[45] = 8;
+                base[10] = 10;
+                base[129..=255].fill(5);
+            }
+            3 => {
+                base[129..=255].fill(60);
+                base[128] = 40;
+                base[97..=122].fill(25);
+                base[65..=90].fill(15);
+                base[34] = 5;
+            }
+            4 => {
+                base[32] = 200;
+                base[10] = 50;
+            }
+            5 => {
+                base[32] = 200;
+            }
+            6 => {
+                base[10] = 30;
+                base[129..=255].fill(25);
+                base[128] = 40;
+                base[65..=90].fill(20);
+            }
+            7 => {
+                base[129..=255].fill(80);
             }
+            8 => {
+                base[97..=122].fill(80);
+            }
+            _ => {}
         }
 
         base
     }
 
-    /// Compute KN-smoothed float distribution over all 256 bytes.
-    pub(crate) fn distribution_f(&self) -> [f64; 256] {
-        let mut hashes = [0u32; 7];
-        let n = self.hist.len();
-        let max_order = std::cmp::min(self.mo + 1, n + 1);
-        for order in 0..max_order {
-            hashes[order] = match self.hash(order) {
-                Some(h) => h,
-                None => 0,
+    /// Order -1 counts for each previous-byte class: unigram frequencies
+    /// plus the class bias, with their inverse total.
+    fn base_mixes(freq: &[u32; 256]) -> Vec<([u32; 256], f64)> {
+        (0..BASE_CLASSES)
+            .map(|class| {
+                let class_base = Self::class_bias(class);
+                let mut mixed = [0u32; 256];
+                let mut freq_total: u64 = 0;
+                for b in 0..256 {
+                    mixed[b] = freq[b] + class_base[b];
+                    freq_total += mixed[b] as u64;
+                }
+                (mixed, 1.0 / freq_total as f64)
+            })
+            .collect()
+    }
+
+    /// Order -1 counts after the last byte of `hist`, with their inverse total.
+    #[inline]
+    fn base_mix(&self, hist: &History) -> (&[u32; 256], f64) {
+        let (mixed, inv_total) = &self.bases[Self::prev_class(hist.last())];
+        (mixed, *inv_total)
+    }
+
+    /// Escape-coded distribution: each order, longest first, takes the mass
+    /// its SEE cell does not give to escaping, shared over its symbols not
+    /// already seen in a longer order. What escapes past order 0 goes to
+    /// the order -1 counts of the remaining symbols.
+    fn distribution_escape(
+        &self,
+        see: &See,
+        mixed: &[u32; 256],
+        inv_freq_total: f64,
+        order_hashes: &[u32],
+        n_active: usize,
+    ) -> [f64; 256] {
+        let mut dist = [0.0f64; 256];
+        let mut excluded = [false; 256];
+        let mut remaining = 1.0f64;
+        let mut seen = 0;
+        for i in (0..n_active).rev() {
+            let d = match self.ctx[i].get(order_hashes[i]) {
+                Some(d) => d,
+                None => continue,
             };
+            let (total, unique) = escape_stats(&d, &excluded);
+            if unique == 0 {
+                continue;
+            }
+            let esc = see.escape(See::context(i, unique, total, seen), unique, total);
+            let scale = remaining * (1.0 - esc) / total as f64;
+            for (sym, count) in d.iter() {
+                if !excluded[sym as usize] {
+                    dist[sym as usize] += scale * count as f64;
+                    excluded[sym as usize] = true;
+                    seen += 1;
+                }
+            }
+            remaining *= esc;
+        }
+
+        let rest: u64 = (0..256).filter(|&b| !excluded[b]).map(|b| mixed[b] as u64).sum();
+        if rest > 0 {
+            let scale = remaining / rest as f64;
+            for b in 0..256 {
+                if !excluded[b] {
+                    dist[b] += scale * mixed[b] as f64;
+                }
+            }
+        }
+
+        for b in 0..256 {
+            dist[b] = (1.0 - ESCAPE_FLOOR) * dist[b] + ESCAPE_FLOOR * mixed[b] as f64 * inv_freq_total;
         }
-        self.distribution_f_cached(&hashes, max_order)
+        dist
+    }
+
+    /// Teach the SEE cells whether `byte` escaped from each order the
+    /// distribution consulted; call before its counts are updated.
+    fn update_see(&mut self, byte: u8, order_hashes: &[u32], n_active: usize) {
+        let see = match &mut self.see {
+            Some(see) => see,
+            None => return,
+        };
+        let mut excluded = [false; 256];
+        let mut seen = 0;
+        for i in (0..n_active).rev() {
+            let d = match self.ctx[i].get(order_hashes[i]) {
+                Some(d) => d,
+                None => continue,
+            };
+            let (total, unique) = escape_stats(&d, &excluded);
+            if unique == 0 {
+                continue;
+            }
+            let found = !excluded[byte as usize] && d.iter().any(|(sym, _)| sym == byte);
+            see.update(See::context(i, unique, total, seen), unique, total, !found);
+            if found {
+                return;
+            }
+            for (sym, _) in d.iter() {
+                if !excluded[sym as usize] {
+                    excluded[sym as usize] = true;
+                    seen += 1;
+                }
+            }
+        }
+    }
+
+    /// Compute KN-smoothed float distribution over all 256 bytes.
+    pub(crate) fn distribution_f(&self, hist: &History) -> [f64; 256] {
+        let (hashes, n_active) = self.order_hashes(hist);
+        self.distribution_f_cached(hist, &hashes, n_active)
     }
 
     /// Compute KN-smoothed float distribution using pre-computed order hashes.
     pub(crate) fn distribution_f_cached(
         &self,
-        order_hashes: &[u32; 7],
-        max_order: usize,
+        hist: &History,
+        order_hashes: &[u32],
+        n_active: usize,
     ) -> [f64; 256] {
-        let base_arr: &[u32; 256] = match &self.base_freq {
-            Some(bf) => bf,
-            None => &CHAR_FREQ,
-        };
-        let class_base = self.class_base();
-
-        let mut mixed = [0u32; 256];
-        let mut freq_total: u64 = 0;
-        for b in 0..256 {
-            mixed[b] = base_arr[b] + class_base[b];
-            freq_total += mixed[b] as u64;
+        let (mixed, inv_freq_total) = self.base_mix(hist);
+        if let Some(see) = &self.see {
+            return self.distribution_escape(see, mixed, inv_freq_total, order_hashes, n_active);
         }
-        let inv_freq_total = 1.0 / freq_total as f64;
 
         let mut dist = [0.0f64; 256];
         for b in 0..256 {
             dist[b] = mixed[b] as f64 * inv_freq_total;
         }
 
-        let limit = std::cmp::min(self.mo + 1, max_order);
-        for order in 0..limit {
-            let h = order_hashes[order];
-            let d = match self.ctx[order].get(h) {
+        for (ctx, &h) in self.ctx[..n_active].iter().zip(order_hashes) {
+            let d = match ctx.get(h) {
                 Some(d) => d,
                 None => continue,
             };
-            let c_total = d.total();
+            let c_total = d.total;
             if c_total == 0 {
                 continue;
             }
-            let n_unique = d.len() as f64;
             let inv_c_total = 1.0 / c_total as f64;
+            if self.adaptive {
+                let disc = ctx.discounts();
+                let discount = |count: u32| disc[count.min(3) as usize - 1];
+                let lam = d.iter().map(|(_, count)| discount(count)).sum::<f64>() * inv_c_total;
+                for p in dist.iter_mut() {
+                    *p *= lam;
+                }
+                for (sym, count) in d.iter() {
+                    dist[sym as usize] += (count as f64 - discount(count)) * inv_c_total;
+                }
+                continue;
+            }
+            let n_unique = d.len() as f64;
             let lam = DISCOUNT * n_unique * inv_c_total;
 
-            let mut new_dist = [0.0f64; 256];
-            for b in 0..256 {
-                new_dist[b] = lam * dist[b];
+            for p in dist.iter_mut() {
+                *p *= lam;
             }
-            for &(sym, count) in d.iter() {
+            for (sym, count) in d.iter() {
                 let direct = (count as f64 - DISCOUNT).max(0.0) * inv_c_total;
-                new_dist[sym as usize] += direct;
+                dist[sym as usize] += direct;
             }
-            dist = new_dist;
         }
 
-        let eps = 0.10f64;
+        let eps = KN_FLOOR;
         let inv_safety_total = inv_freq_total;
         for b in 0..256 {
             dist[b] = (1.0 - eps) * dist[b] + eps * mixed[b] as f64 * inv_safety_total;
@@ -389,19 +976,111 @@ impl PPM {
         dist
     }
 
+    /// Put the distribution `distribution_f_cached` gives into `tree` (not
+    /// yet summed up). Equal up to rounding.
+    pub(crate) fn tree_distribution(
+        &self,
+        hist: &History,
+        order_hashes: &[u32],
+        n_active: usize,
+        tree: &mut TreeDist,
+    ) {
+        let (mixed, inv_freq_total) = self.base_mix(hist);
+        tree.reset(mixed);
+        if let Some(see) = &self.see {
+            self.tree_escape(see, mixed, inv_freq_total, order_hashes, n_active, tree);
+            return;
+        }
+
+        // Longest order first, so each order's terms carry the product of
+        // the escape weights of the orders above it
+        let mut scale = 1.0 - KN_FLOOR;
+        for i in (0..n_active).rev() {
+            let d = match self.ctx[i].get(order_hashes[i]) {
+                Some(d) => d,
+                None => continue,
+            };
+            if d.total == 0 {
+                continue;
+            }
+            let inv_c_total = 1.0 / d.total as f64;
+            let lam = if self.adaptive {
+                let disc = self.ctx[i].discounts();
+                let discount = |count: u32| disc[count.min(3) as usize - 1];
+                for (sym, count) in d.iter() {
+                    tree.terms[sym as usize] += scale * (count as f64 - discount(count)) * inv_c_total;
+                }
+                d.iter().map(|(_, count)| discount(count)).sum::<f64>() * inv_c_total
+            } else {
+                for (sym, count) in d.iter() {
+                    let direct = (count as f64 - DISCOUNT).max(0.0) * inv_c_total;
+                    tree.terms[sym as usize] += scale * direct;
+                }
+                DISCOUNT * d.len() as f64 * inv_c_total
+            };
+            scale *= lam;
+        }
+        tree.base_w = (scale + KN_FLOOR) * inv_freq_total;
+    }
+
+    /// `distribution_escape` as a `TreeDist`. The order -1 share of the
+    /// excluded bytes is taken back with negative terms.
+    fn tree_escape(
+        &self,
+        see: &See,
+        mixed: &[u32; 256],
+        inv_freq_total: f64,
+        order_hashes: &[u32],
+        n_active: usize,
+        tree: &mut TreeDist,
+    ) {
+        let mut excluded = [false; 256];
+        let mut remaining = 1.0 - ESCAPE_FLOOR;
+        let mut seen = 0;
+        for i in (0..n_active).rev() {
+            let d = match self.ctx[i].get(order_hashes[i]) {
+                Some(d) => d,
+                None => continue,
+            };
+            let (total, unique) = escape_stats(&d, &excluded);
+            if unique == 0 {
+                continue;
+            }
+            let esc = see.escape(See::context(i, unique, total, seen), unique, total);
+            let scale = remaining * (1.0 - esc) / total as f64;
+            for (sym, count) in d.iter() {
+                if !excluded[sym as usize] {
+                    tree.terms[sym as usize] += scale * count as f64;
+                    excluded[sym as usize] = true;
+                    seen += 1;
+                }
+            }
+            remaining *= esc;
+        }
+
+        let rest: u64 = (0..256).filter(|&b| !excluded[b]).map(|b| mixed[b] as u64).sum();
+        if rest > 0 {
+            let scale = remaining / rest as f64;
+            tree.base_w += scale;
+            for b in 0..256 {
+                if excluded[b] {
+                    tree.terms[b] -= scale * mixed[b] as f64;
+                }
+            }
+        }
+        tree.base_w += ESCAPE_FLOOR * inv_freq_total;
+    }
+
     /// Compute distribution with LZP mixing, returns integer counts for arithmetic coding.
-    fn distribution(&self, match_byte: i32, match_len: i32) -> [u32; 256] {
-        let mut dist = self.distribution_f();
+    fn distribution(&self, hist: &History, match_byte: i32, match_len: i32) -> [u32; 256] {
+        let mut dist = self.distribution_f(hist);
 
         if match_byte >= 0 && match_len >= 4 {
             let lzp_w = (match_len as f64 * 0.04).min(0.65);
             let rest = 0.02 / 255.0;
-            for b in 0..256 {
-                if b as i32 == match_byte {
-                    dist[b] = (1.0 - lzp_w) * dist[b] + lzp_w * 0.98;
-                } else {
-                    dist[b] = (1.0 - lzp_w) * dist[b] + lzp_w * rest;
-                }
+            for (b, p) in dist.iter_mut().enumerate() {
+                let target = if b as i32 == match_byte { 0.98 } else { rest };
+                *p = (1.0 - lzp_w) * *p + lzp_w * target;
             }
         }
 
@@ -412,32 +1091,19 @@ impl PPM {
         counts
     }
 
-    pub fn encode_byte(&mut self, byte: u8, enc: &mut AEnc, match_byte: i32, match_len: i32) {
-        let counts = self.distribution(match_byte, match_len);
-        let mut cum: u64 = 0;
-        let mut cl: u64 = 0;
-        let mut ch: u64 = 0;
-        for b in 0..256 {
-            if b as u8 == byte {
-                cl = cum;
-                ch = cum + counts[b] as u64;
-            }
-            cum += counts[b] as u64;
-        }
-        enc.encode(cl, ch, cum);
-        self.update(byte);
+    /// Code `byte` and count it; the caller then pushes it to `hist`.
+    pub fn encode_byte(&mut self, hist: &History, byte: u8, enc: &mut Encoder, match_byte: i32, match_len: i32) {
+        let counts = self.distribution(hist, match_byte, match_len);
+        enc.encode_counts(&counts, byte);
+        self.update(hist, byte);
     }
 
-    pub fn decode_byte(&mut self, dec: &mut ADec, match_byte: i32, match_len: i32) -> u8 {
-        let counts = self.distribution(match_byte, match_len);
-        let mut cum_arr = [0u64; 257];
-        for b in 0..256 {
-            cum_arr[b + 1] = cum_arr[b] + counts[b] as u64;
-        }
-        let total = cum_arr[256];
-        let idx = dec.decode(&cum_arr, total);
-        self.update(idx as u8);
-        idx as u8
+    /// Decode a byte and count it; the caller then pushes it to `hist`.
+    pub fn decode_byte(&mut self, hist: &History, dec: &mut Decoder, match_byte: i32, match_len: i32) -> u8 {
+        let counts = self.distribution(hist, match_byte, match_len);
+        let byte = dec.decode_counts(&counts);
+        self.update(hist, byte);
+        byte
     }
 }
 
@@ -445,3 +1111,24 @@ impl PPM {
 fn isqrt(val: u32) -> u32 {
     (val as f64).sqrt() as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_sym_matches_position() {
+        let mut state = 12345u32;
+        for len in 0..40 {
+            let syms: Vec<u8> = (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((state >> 29) as u8).wrapping_mul(0x41)
+                })
+                .collect();
+            for sym in [0, 0x41, 0x82, 0xc3, 0x04, 0xff] {
+                assert_eq!(find_sym(&syms, sym), syms.iter().position(|&s| s == sym), "{syms:?} {sym}");
+            }
+        }
+    }
+}
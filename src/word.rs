@@ -0,0 +1,62 @@
+use crate::dict::CAP_MARKER;
+
+/// Word-level contexts over the preprocessed stream.
+///
+/// Words are runs of ASCII letters and digits (case-folded); each dictionary
+/// token 129..=255 is a complete word of its own. The contexts pair the
+/// current word prefix with the words before it, so the bit models learn
+/// word-to-word dependencies such as "of the" or "in order to".
+#[derive(Clone, Default)]
+pub struct WordContexts {
+    /// Hash of the current partial word, 0 between words
+    cur: u32,
+    /// Hashes of the last two complete words, most recent first
+    prev: [u32; 2],
+    /// Characters of the current word seen so far
+    pos: u32,
+}
+
+impl WordContexts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn finish_word(&mut self, h: u32) {
+        self.prev = [h, self.prev[0]];
+        self.cur = 0;
+        self.pos = 0;
+    }
+
+    pub fn update(&mut self, byte: u8) {
+        if byte > CAP_MARKER {
+            if self.cur != 0 {
+                self.finish_word(self.cur);
+            }
+            self.finish_word((byte as u32 + 1).wrapping_mul(0x2C9277B5));
+        } else if byte.is_ascii_alphanumeric() {
+            let c = byte.to_ascii_lowercase() as u32;
+            self.cur = (self.cur ^ c).wrapping_mul(16777619) | 1;
+            self.pos += 1;
+        } else if byte != CAP_MARKER && self.cur != 0 {
+            self.finish_word(self.cur);
+        }
+    }
+
+    /// Byte-level base hashes for the next byte: (previous word + prefix,
+    /// previous two words + prefix, position in word + previous byte).
+    /// A context is `None` until there is a previous word to pair with.
+    pub fn contexts(&self, last: u8) -> [Option<u32>; 3] {
+        let prefix = self.cur.wrapping_mul(2246822519);
+        let bigram = (self.prev[0] != 0).then(|| {
+            let h = self.prev[0].wrapping_mul(2654435761) ^ prefix;
+            h.wrapping_mul(16777619) ^ 0x89ABCDEF
+        });
+        let trigram = (self.prev[1] != 0).then(|| {
+            let h = (self.prev[1].wrapping_mul(3266489917) ^ self.prev[0]).wrapping_mul(2654435761)
+                ^ prefix;
+            h.wrapping_mul(16777619) ^ 0x9ABCDEF0
+        });
+        let pos = (self.pos.min(15) << 8 | last as u32).wrapping_mul(2654435761);
+        [bigram, trigram, Some(pos.wrapping_mul(16777619) ^ 0xABCDEF01)]
+    }
+}
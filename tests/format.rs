@@ -0,0 +1,117 @@
+//! Decoding of the frozen V7–V10 formats and parsing of the V10 model config.
+//!
+//! The fixtures under `tests/data` were written from `sample.txt`, V7–V9 by
+//! the baseline encoder: V7 with PPM and LZP, V8 with the single-threaded
+//! mixer, and V9 as two blocks coded from one pretrained mixer. V10 was
+//! written at the default level, through the fixed-point mixer.
+
+use claudcompress::config::{
+    ModelConfig, CODEC_PPM, HASH_NEWEST_FIRST, MAX_LEVEL, MAX_PROB_BITS, MIN_LEVEL, MIN_PROB_BITS,
+};
+use claudcompress::format::read_header_v10;
+use claudcompress::quantum_decompress_threads;
+
+const SAMPLE: &str = include_str!("data/sample.txt");
+
+fn decode_fixture(data: &[u8]) {
+    assert_eq!(quantum_decompress_threads(data, 1).unwrap(), SAMPLE);
+    assert_eq!(quantum_decompress_threads(data, 2).unwrap(), SAMPLE);
+}
+
+#[test]
+fn decodes_v7() {
+    decode_fixture(include_bytes!("data/sample.v7"));
+}
+
+#[test]
+fn decodes_v8() {
+    decode_fixture(include_bytes!("data/sample.v8"));
+}
+
+#[test]
+fn decodes_v9() {
+    decode_fixture(include_bytes!("data/sample.v9"));
+}
+
+#[test]
+fn decodes_v10() {
+    let v10 = include_bytes!("data/sample.v10");
+    let cfg = ModelConfig::from_bytes(read_header_v10(v10).unwrap().config).unwrap();
+    assert_eq!(cfg.fixed_point, 1);
+    decode_fixture(v10);
+}
+
+#[test]
+fn rejects_damaged_headers() {
+    let v9 = include_bytes!("data/sample.v9");
+    assert!(quantum_decompress_threads(b"QICM", 1).is_err());
+    assert!(quantum_decompress_threads(b"ABCD\x09\x00\x00\x00\x00\x00", 1).is_err());
+    assert!(quantum_decompress_threads(&v9[..v9.len() - 1], 1).is_err());
+
+    let mut bad_version = v9.to_vec();
+    bad_version[4] = 99;
+    assert!(quantum_decompress_threads(&bad_version, 1).is_err());
+}
+
+#[test]
+fn config_bytes_round_trip() {
+    for cfg in [ModelConfig::legacy(), ModelConfig::default()] {
+        assert_eq!(ModelConfig::from_bytes(&cfg.to_bytes()).unwrap(), cfg);
+    }
+}
+
+#[test]
+fn fitted_configs_validate() {
+    for level in MIN_LEVEL..=MAX_LEVEL {
+        let cfg = ModelConfig::for_level(level).unwrap();
+        for len in [0, 1, 1000, 1 << 20, usize::MAX / 2] {
+            let fitted = cfg.fitted_to(len);
+            fitted.validate().unwrap();
+            assert!(fitted.history_window <= cfg.history_window);
+            assert!(fitted.lzp_table <= cfg.lzp_table);
+            assert!(fitted.bit_table_bits <= cfg.bit_table_bits);
+            assert!(fitted.match_table_bits <= cfg.match_table_bits);
+        }
+        assert_eq!(cfg.fitted_to(1000).match_table_bits, 12);
+        assert_eq!(cfg.fitted_to(usize::MAX / 2), cfg);
+    }
+}
+
+#[test]
+fn rejects_truncated_or_padded_configs() {
+    let bytes = ModelConfig::default().to_bytes();
+    assert!(ModelConfig::from_bytes(&[]).is_err());
+    for len in 0..bytes.len() {
+        assert!(ModelConfig::from_bytes(&bytes[..len]).is_err(), "accepted {len} bytes");
+    }
+    let mut padded = bytes.clone();
+    padded.push(0);
+    assert!(ModelConfig::from_bytes(&padded).is_err());
+}
+
+#[test]
+fn rejects_invalid_configs() {
+    let cases: [fn(&mut ModelConfig); 15] = [
+        |c| c.orders = vec![0, 2, 1],
+        |c| c.orders = (0..=16).collect(),
+        |c| c.ppm_orders = vec![0, 33],
+        |c| c.matches = vec![1],
+        |c| c.matches = vec![8; 5],
+        |c| c.match_table_bits = 9,
+        |c| c.lzp_chain = 3,
+        |c| c.history_window = c.lzp_window - 1,
+        |c| c.codec = CODEC_PPM + 1,
+        |c| c.prob_bits = MIN_PROB_BITS - 1,
+        |c| c.prob_bits = MAX_PROB_BITS + 1,
+        |c| c.bit_table_bits = 40,
+        |c| c.context_hash = HASH_NEWEST_FIRST + 1,
+        |c| c.residual_nn = 2,
+        |c| c.level = MAX_LEVEL + 1,
+    ];
+    for (i, break_cfg) in cases.iter().enumerate() {
+        let mut cfg = ModelConfig::default();
+        break_cfg(&mut cfg);
+        assert!(cfg.validate().is_err(), "case {i} validated");
+        assert!(ModelConfig::from_bytes(&cfg.to_bytes()).is_err(), "case {i} parsed");
+    }
+}
@@ -0,0 +1,80 @@
+//! PPM's memory budget, checked against what the heap really holds.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use claudcompress::history::History;
+use claudcompress::ppm::{PrunePolicy, PPM};
+
+/// Counts the bytes live on the heap.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// History window for the test, small next to the budget
+const WINDOW_BITS: u8 = 12;
+/// Heap the model holds outside its tables: the order -1 bases and the
+/// free lists' spare capacity
+const SLACK: usize = 1 << 14;
+
+/// Text with a large vocabulary, so the high orders keep finding new
+/// contexts and the tables outgrow any small budget.
+fn words(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491u32;
+    let mut text = Vec::with_capacity(len);
+    while text.len() < len {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let word_len = 2 + state as usize % 7;
+        for i in 0..word_len {
+            text.push(b'a' + (state >> (i * 3) & 15) as u8);
+        }
+        text.push(if state.is_multiple_of(11) { b'\n' } else { b' ' });
+    }
+    text
+}
+
+/// Code `text` under `budget`, checking after every byte that the model's
+/// count stays within it and matches what the heap holds. Returns the peak
+/// count.
+fn peak_memory(policy: PrunePolicy, budget: usize, text: &[u8]) -> usize {
+    let before = LIVE.load(Ordering::Relaxed);
+    let mut ppm = PPM::new(6).with_budget(budget, policy);
+    let mut hist = History::new(WINDOW_BITS, ppm.orders(), true);
+    let mut peak = 0;
+    for &byte in text {
+        ppm.update(&hist, byte);
+        hist.push(byte);
+        let heap = LIVE.load(Ordering::Relaxed) - before - (1 << WINDOW_BITS);
+        assert!(ppm.memory() <= budget, "counted {} bytes, budget {budget}", ppm.memory());
+        assert!(heap.abs_diff(ppm.memory()) <= SLACK, "counted {} bytes, heap holds {heap}", ppm.memory());
+        peak = peak.max(ppm.memory());
+    }
+    peak
+}
+
+#[test]
+fn stays_under_budget() {
+    let text = words(200_000);
+    let budget = 1 << 18;
+    for policy in [PrunePolicy::LowCounts, PrunePolicy::Restart] {
+        let peak = peak_memory(policy, budget, &text);
+        assert!(peak > budget / 2, "{policy:?} never neared the budget");
+    }
+}
@@ -0,0 +1,92 @@
+//! Round trips through the V10 format under each model choice.
+
+use claudcompress::config::{
+    ModelConfig, CODEC_MIXER, CODEC_PPM, LR_DECAY, LR_ERROR, LR_FIXED, LR_RMS, MAX_LEVEL, MIN_LEVEL,
+    PPM_ADAPTIVE, PPM_ESCAPE, PPM_INTERPOLATED,
+};
+use claudcompress::format::read_header_v10;
+use claudcompress::{quantum_compress_with, quantum_decompress_threads};
+
+const SAMPLE: &str = include_str!("data/sample.txt");
+
+fn round_trip(cfg: &ModelConfig) {
+    let packed = quantum_compress_with(SAMPLE, cfg, 1);
+    assert!(packed.len() < SAMPLE.len() / 2, "{} bytes", packed.len());
+    assert_eq!(quantum_decompress_threads(&packed, 1).unwrap(), SAMPLE);
+}
+
+/// `len` bytes of sample lines in pseudo-random order, each tagged with a
+/// pseudo-random number so that repeats are long but not exact.
+fn generated(len: usize) -> String {
+    let lines: Vec<&str> = SAMPLE.lines().collect();
+    let mut state = 0x2545_f491_u32;
+    let mut text = String::with_capacity(len + 256);
+    while text.len() < len {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        let line = lines[(state >> 8) as usize % lines.len()];
+        text.push_str(&format!("{} {}\n", state >> 22, line));
+    }
+    text
+}
+
+#[test]
+fn lr_schedules() {
+    for lr_schedule in [LR_FIXED, LR_DECAY, LR_RMS, LR_ERROR] {
+        round_trip(&ModelConfig { lr_schedule, ..ModelConfig::default() });
+    }
+}
+
+#[test]
+fn codecs_and_ppm_models() {
+    for codec in [CODEC_MIXER, CODEC_PPM] {
+        for ppm_model in [PPM_INTERPOLATED, PPM_ESCAPE, PPM_ADAPTIVE] {
+            round_trip(&ModelConfig { codec, ppm_model, ..ModelConfig::default() });
+        }
+    }
+}
+
+#[test]
+fn fixed_and_float_mixers() {
+    for fixed_point in [0, 1] {
+        for lr_schedule in [LR_FIXED, LR_RMS] {
+            round_trip(&ModelConfig { fixed_point, lr_schedule, ..ModelConfig::default() });
+        }
+    }
+}
+
+#[test]
+fn levels() {
+    for level in MIN_LEVEL..=MAX_LEVEL {
+        round_trip(&ModelConfig::for_level(level).unwrap());
+    }
+}
+
+#[test]
+fn with_residual_nn() {
+    round_trip(&ModelConfig { residual_nn: 1, ..ModelConfig::default() });
+}
+
+#[test]
+fn windows_wrap() {
+    // 256 KB through a 64 KB history, a 4 KB LZP window and a 1K-entry LZP
+    // table: the ring wraps several times and the LZP table evicts throughout
+    let text = generated(256 << 10);
+    let cfg = ModelConfig {
+        history_window: 16,
+        lzp_window: 12,
+        lzp_table: 10,
+        ..ModelConfig::for_level(2).unwrap()
+    };
+    let packed = quantum_compress_with(&text, &cfg, 1);
+    assert!(packed.len() < text.len() / 4, "{} bytes", packed.len());
+    assert_eq!(quantum_decompress_threads(&packed, 1).unwrap(), text);
+}
+
+#[test]
+fn threaded_blocks() {
+    let text = generated(256 << 10);
+    let packed = quantum_compress_with(&text, &ModelConfig::for_level(2).unwrap(), 4);
+    assert!(read_header_v10(&packed).unwrap().blocks.len() > 1);
+    assert_eq!(quantum_decompress_threads(&packed, 4).unwrap(), text);
+    assert_eq!(quantum_decompress_threads(&packed, 1).unwrap(), text);
+}